@@ -4,24 +4,60 @@
 )]
 
 use std::env;
+use std::path::Path;
 use std::process::Command;
+use std::thread;
+use std::time::Duration;
 
 use notmuch_more::Database;
+use notmuch_more::RemoteContentPolicies;
+use notmuch_more::Templates;
+use notmuch_more::attachment_text;
+use notmuch_more::attachments;
 use notmuch_more::compose;
+use notmuch_more::invites;
 use notmuch_more::parse;
 use notmuch_more::parse::EmlBody;
 use notmuch_more::parse::EmlMeta;
 use notmuch_more::parse::EmlParseError;
+use notmuch_more::parse::Mailbox;
+use notmuch_more::parse::RemoteContent;
 use notmuch_more::query;
 use notmuch_more::smtp;
 use notmuch_more::tags;
+use notmuch_more::templates;
+use notmuch_more::unsubscribe;
 
 mod error;
 use self::error::AmailError;
 
+const INDEX_BATCH: usize = 50;
+/// How often to look for new mail to index.
+const INDEX_POLL: Duration = Duration::from_secs(60);
+
 struct State {
     db: Database,
+    remote_content: RemoteContentPolicies,
     smtp: smtp::Smtp,
+    templates: Templates,
+    /// Whose `Authentication-Results` to believe: our own receiving servers',
+    /// which remove any forged ones from elsewhere.
+    trusted_authserv_ids: Vec<String>,
+}
+
+fn apply_template(
+    state: &State,
+    draft: &mut compose::ReplyTemplate,
+    template: Option<String>,
+    original: Option<&EmlMeta>,
+) -> Result<(), AmailError> {
+    let template = template.map(|t| state.templates.get(&t)).transpose()?;
+    let signature = match draft.meta.from.first() {
+        Some(from) => state.templates.signature(&from.address)?,
+        None => None,
+    };
+    compose::apply_template(draft, template.as_ref(), signature.as_ref(), original);
+    Ok(())
 }
 
 #[tauri::command]
@@ -48,7 +84,7 @@ fn list_eml(
     query: String,
 ) -> Result<Vec<Result<EmlMeta, EmlParseError>>, AmailError> {
     let db = state.db.open_ro()?;
-    Ok(query::list_eml(&db, query)?)
+    Ok(query::list_eml(&db, query, &state.trusted_authserv_ids)?)
 }
 
 #[tauri::command]
@@ -58,9 +94,83 @@ fn list_tags(state: tauri::State<State>) -> Result<Vec<String>, AmailError> {
 }
 
 #[tauri::command]
-fn view_eml(state: tauri::State<State>, id: String) -> Result<EmlBody, AmailError> {
+fn view_eml(
+    state: tauri::State<State>,
+    id: String,
+    allow_remote: Option<bool>,
+) -> Result<EmlBody, AmailError> {
+    let db = state.db.open_ro()?;
+    let meta = parse::parse_meta(&db, id.clone())?;
+    let remote = match (allow_remote, meta.from.first()) {
+        (Some(true), _) => RemoteContent::Allow,
+        (_, Some(from)) => state.remote_content.policy(&from.address)?,
+        (_, None) => RemoteContent::Block,
+    };
+    Ok(parse::parse_eml_with_remote(&db, id, remote)?.1)
+}
+
+/// Sets whether to load remote content from an address or a whole domain.
+#[tauri::command]
+fn set_remote_content_policy(
+    state: tauri::State<State>,
+    sender: String,
+    policy: Option<RemoteContent>,
+) -> Result<(), AmailError> {
+    Ok(state.remote_content.set_policy(&sender, policy)?)
+}
+
+/// The decoded part, as raw bytes rather than (much larger) serialised JSON.
+#[tauri::command]
+fn get_part(
+    state: tauri::State<State>,
+    id: String,
+    part_id: String,
+) -> Result<tauri::ipc::Response, AmailError> {
+    let db = state.db.open_ro()?;
+    Ok(tauri::ipc::Response::new(attachments::read_part(
+        &db, &id, &part_id,
+    )?))
+}
+
+#[tauri::command]
+fn save_part(
+    state: tauri::State<State>,
+    id: String,
+    part_id: String,
+    path: String,
+) -> Result<(), AmailError> {
+    let db = state.db.open_ro()?;
+    Ok(attachments::save_part(
+        &db,
+        &id,
+        &part_id,
+        Path::new(&path),
+    )?)
+}
+
+#[tauri::command]
+fn save_part_temp(
+    state: tauri::State<State>,
+    id: String,
+    part_id: String,
+) -> Result<String, AmailError> {
+    let db = state.db.open_ro()?;
+    Ok(attachments::save_part_temp(&db, &id, &part_id)?
+        .display()
+        .to_string())
+}
+
+#[tauri::command]
+fn save_attachments(
+    state: tauri::State<State>,
+    id: String,
+    dir: String,
+) -> Result<Vec<String>, AmailError> {
     let db = state.db.open_ro()?;
-    Ok(parse::parse_eml(&db, id)?.1)
+    Ok(attachments::save_attachments(&db, &id, Path::new(&dir))?
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect())
 }
 
 #[tauri::command]
@@ -75,24 +185,152 @@ fn send_eml(
     meta: EmlMeta,
     body: String,
     attachments: Vec<compose::Attachment>,
+    options: compose::ComposeOptions,
 ) -> Result<(), AmailError> {
     let db = state.db.open_rw()?;
 
+    state
+        .smtp
+        .send(&db, meta.destinations()?, meta.resolve_sender()?, |eml| {
+            compose::write_message(&meta, body, attachments, &options, eml)
+        })?;
+
+    if options.invite.is_some() {
+        invites::process_calendars(&db)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn get_attendance(
+    state: tauri::State<State>,
+    id: String,
+) -> Result<Vec<(String, String)>, AmailError> {
+    let db = state.db.open_ro()?;
+    Ok(invites::attendance(&db, id)?)
+}
+
+#[tauri::command]
+fn get_delivery_path(
+    state: tauri::State<State>,
+    id: String,
+) -> Result<Vec<parse::Hop>, AmailError> {
+    let db = state.db.open_ro()?;
+    Ok(parse::delivery_path(&db, id)?)
+}
+
+#[tauri::command]
+fn reply_calendar(
+    state: tauri::State<State>,
+    id: String,
+    partstat: parse::PartStat,
+) -> Result<(), AmailError> {
+    let db = state.db.open_rw()?;
+    let reply = compose::calendar_reply(&db, id, partstat)?;
+
     Ok(state.smtp.send(
         &db,
-        meta.destinations()?,
-        meta.resolve_sender()?,
-        compose::format_message(&meta, body, attachments)?,
+        reply.meta.destinations()?,
+        reply.meta.resolve_sender()?,
+        |eml| reply.write_to(eml),
     )?)
 }
 
+#[tauri::command]
+fn resend_eml(
+    state: tauri::State<State>,
+    id: String,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+) -> Result<(), AmailError> {
+    let db = state.db.open_rw()?;
+    Ok(state.smtp.resend(&db, &id, &from, &to)?)
+}
+
 #[tauri::command]
 fn get_reply_template(
     state: tauri::State<State>,
     id: String,
+    template: Option<String>,
 ) -> Result<compose::ReplyTemplate, AmailError> {
     let db = state.db.open_rw()?;
-    Ok(compose::template_reply(&db, id)?)
+    let mut draft = compose::template_reply(&db, id.clone())?;
+    let original = parse::parse_meta(&db, id)?;
+    apply_template(&state, &mut draft, template, Some(&original))?;
+    Ok(draft)
+}
+
+#[tauri::command]
+fn get_forward_template(
+    state: tauri::State<State>,
+    id: String,
+    template: Option<String>,
+) -> Result<compose::ReplyTemplate, AmailError> {
+    let db = state.db.open_ro()?;
+    let mut draft = compose::template_forward(&db, id.clone())?;
+    let original = parse::parse_meta(&db, id)?;
+    apply_template(&state, &mut draft, template, Some(&original))?;
+    Ok(draft)
+}
+
+#[tauri::command]
+fn get_new_template(
+    state: tauri::State<State>,
+    from: Vec<Mailbox>,
+    template: Option<String>,
+) -> Result<compose::ReplyTemplate, AmailError> {
+    let mut draft = compose::template_new(from);
+    apply_template(&state, &mut draft, template, None)?;
+    Ok(draft)
+}
+
+#[tauri::command]
+fn list_templates(state: tauri::State<State>) -> Result<Vec<templates::Template>, AmailError> {
+    Ok(state.templates.list()?)
+}
+
+#[tauri::command]
+fn save_template(
+    state: tauri::State<State>,
+    template: templates::Template,
+) -> Result<(), AmailError> {
+    Ok(state.templates.save(template)?)
+}
+
+#[tauri::command]
+fn delete_template(state: tauri::State<State>, name: String) -> Result<(), AmailError> {
+    Ok(state.templates.delete(&name)?)
+}
+
+#[tauri::command]
+fn get_signature(
+    state: tauri::State<State>,
+    identity: String,
+) -> Result<Option<templates::Signature>, AmailError> {
+    Ok(state.templates.signature(&identity)?)
+}
+
+#[tauri::command]
+fn set_signature(
+    state: tauri::State<State>,
+    identity: String,
+    signature: Option<templates::Signature>,
+) -> Result<(), AmailError> {
+    Ok(state.templates.set_signature(&identity, signature)?)
+}
+
+#[tauri::command]
+fn unsubscribe_list(
+    state: tauri::State<State>,
+    id: String,
+) -> Result<unsubscribe::Unsubscribed, AmailError> {
+    let db = state.db.open_ro()?;
+    Ok(unsubscribe::unsubscribe_from(
+        &db,
+        &id,
+        &unsubscribe::SimpleHttpClient::default(),
+        &parse::DnsTxtResolver::system().map_err(notmuch_more::NotmuchMoreError::from)?,
+    )?)
 }
 
 #[tauri::command]
@@ -101,8 +339,21 @@ fn preview_eml(
     meta: EmlMeta,
     body: String,
     attachments: Vec<compose::Attachment>,
+    options: compose::ComposeOptions,
 ) -> Result<String, AmailError> {
-    Ok(compose::format_message(&meta, body, attachments)?)
+    Ok(compose::format_message(&meta, body, attachments, &options)?)
+}
+
+fn config_dir() -> String {
+    env::var("AMAIL_CONFIG_DIR").unwrap_or_else(|_| {
+        format!(
+            "{}/amail",
+            env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| format!(
+                "{}/.config",
+                env::var("HOME").expect("Missing $HOME")
+            ))
+        )
+    })
 }
 
 fn main() {
@@ -115,29 +366,97 @@ fn main() {
     )
     .expect("Non-UTF8 database.path");
     db_path = db_path.trim().to_string();
-    let db = Database::new(db_path);
+    let db = Database::new(db_path.clone());
+    if let Err(e) = db.open_rw().and_then(|d| invites::process_calendars(&d)) {
+        println!("[WARN] Failed to process calendar messages: {e}");
+    }
+
+    // In batches, so as to only hold the write lock briefly at a time, and
+    // polling for new mail once caught up.
+    thread::spawn(move || {
+        let index_db = Database::new(db_path);
+        loop {
+            let pause = match index_db
+                .open_rw()
+                .and_then(|d| attachment_text::index_attachments(&d, INDEX_BATCH))
+            {
+                Ok(0) => {
+                    if let Err(e) = index_db
+                        .open_rw()
+                        .and_then(|d| invites::process_calendars(&d))
+                    {
+                        println!("[WARN] Failed to process calendar messages: {e}");
+                    }
+                    INDEX_POLL
+                }
+                Ok(n) => {
+                    println!("[TRACE] Indexed attachments of {n} messages");
+                    Duration::from_millis(500)
+                }
+                Err(e) => {
+                    println!("[WARN] Failed to index attachments: {e}");
+                    INDEX_POLL
+                }
+            };
+            thread::sleep(pause);
+        }
+    });
 
-    let smtp = smtp::Smtp::new(
+    let mut smtp = smtp::Smtp::new(
         env::var("SMTP_HOST").expect("Missing $SMTP_HOST"),
         env::var("SMTP_USER").expect("Missing $SMTP_USER"),
         env::var("SMTP_PASS").expect("Missing $SMTP_PASS"),
-    );
+    )
+    .dkim_keys(config_dir());
+    if let Ok(max) = env::var("SMTP_MAX_MESSAGE_SIZE") {
+        smtp = smtp.max_message_size(max.parse().expect("Invalid $SMTP_MAX_MESSAGE_SIZE"));
+    }
 
     tauri::Builder::default()
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_shell::init())
-        .manage(State { db, smtp })
+        .manage(State {
+            db,
+            remote_content: RemoteContentPolicies::new(config_dir()),
+            smtp,
+            templates: Templates::new(config_dir()),
+            trusted_authserv_ids: env::var("AMAIL_TRUSTED_AUTHSERV_IDS")
+                .map(|ids| {
+                    ids.split(',')
+                        .map(|id| id.trim().to_lowercase())
+                        .filter(|id| !id.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
         .invoke_handler(tauri::generate_handler![
             apply_tag,
             count_matches,
+            delete_template,
+            get_attendance,
+            get_delivery_path,
+            get_forward_template,
             get_name,
+            get_new_template,
+            get_part,
             get_reply_template,
+            get_signature,
             list_eml,
             list_tags,
+            list_templates,
             preview_eml,
+            reply_calendar,
+            resend_eml,
             rm_tag,
+            save_attachments,
+            save_part,
+            save_part_temp,
+            save_template,
             send_eml,
+            set_remote_content_policy,
+            set_signature,
+            unsubscribe_list,
             view_eml,
         ])
         .run(tauri::generate_context!())
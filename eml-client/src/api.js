@@ -14,8 +14,41 @@ export const applyTag = (query, tag) => tauri
 
 export const getName = () => tauri.invoke("get_name")
 
-export const getReplyTemplate = (id) => tauri.invoke("get_reply_template", {
+export const deleteTemplate = (name) => tauri.invoke("delete_template", {
+  name,
+})
+
+export const getAttendance = (id) => tauri.invoke("get_attendance", {
+  id,
+})
+
+export const getDeliveryPath = (id) => tauri.invoke("get_delivery_path", {
+  id,
+})
+
+export const getForwardTemplate = (id, template = null) => tauri.invoke("get_forward_template", {
+  id,
+  template,
+})
+
+export const getNewTemplate = (from, template = null) => tauri.invoke("get_new_template", {
+  from,
+  template,
+})
+
+export const getPart = (id, partId) => tauri.invoke("get_part", {
+  id,
+  partId,
+})
+  .then((buf) => new Uint8Array(buf))
+
+export const getReplyTemplate = (id, template = null) => tauri.invoke("get_reply_template", {
   id,
+  template,
+})
+
+export const getSignature = (identity) => tauri.invoke("get_signature", {
+  identity,
 })
 
 export const listEml = (query) => tauri.invoke("list_eml", {
@@ -24,6 +57,8 @@ export const listEml = (query) => tauri.invoke("list_eml", {
 
 export const listTags = () => tauri.invoke("list_tags")
 
+export const listTemplates = () => tauri.invoke("list_templates")
+
 export const countMatches = (query) => tauri.invoke("count_matches", {
   query,
 })
@@ -40,20 +75,68 @@ export const rmTag = (query, tag) => tauri
   }),
   )
 
-export const previewEml = (meta, body, attachments = []) => tauri.invoke("preview_eml", {
+export const previewEml = (meta, body, attachments = [], options = {}) => tauri.invoke("preview_eml", {
   meta,
   body,
   attachments,
+  options,
+})
+
+export const replyCalendar = (id, partstat) => tauri.invoke("reply_calendar", {
+  id,
+  partstat,
 })
 
-export const sendEml = (meta, body, attachments = []) => tauri.invoke("send_eml", {
+export const resendEml = (id, from, to) => tauri.invoke("resend_eml", {
+  id,
+  from,
+  to,
+})
+
+export const saveAttachments = (id, dir) => tauri.invoke("save_attachments", {
+  id,
+  dir,
+})
+
+export const savePart = (id, partId, path) => tauri.invoke("save_part", {
+  id,
+  partId,
+  path,
+})
+
+export const savePartTemp = (id, partId) => tauri.invoke("save_part_temp", {
+  id,
+  partId,
+})
+
+export const saveTemplate = (template) => tauri.invoke("save_template", {
+  template,
+})
+
+export const sendEml = (meta, body, attachments = [], options = {}) => tauri.invoke("send_eml", {
   meta,
   body,
   attachments,
+  options,
+})
+
+export const setRemoteContentPolicy = (sender, policy) => tauri.invoke("set_remote_content_policy", {
+  sender,
+  policy,
+})
+
+export const setSignature = (identity, signature) => tauri.invoke("set_signature", {
+  identity,
+  signature,
 })
 
 export const tagList = () => tauri.invoke("list_tags")
 
-export const viewEml = (id) => tauri.invoke("view_eml", {
+export const unsubscribeList = (id) => tauri.invoke("unsubscribe_list", {
+  id,
+})
+
+export const viewEml = (id, allowRemote = false) => tauri.invoke("view_eml", {
   id,
+  allowRemote,
 })
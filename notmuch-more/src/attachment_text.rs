@@ -0,0 +1,329 @@
+use std::fs;
+use std::io::Cursor;
+use std::io::Read;
+use std::panic;
+use std::path::Path;
+
+use anyhow::anyhow;
+use itertools::Itertools;
+use mailparse::DispositionType;
+use notmuch::Database;
+use quick_xml::events::Event;
+use regex::Captures;
+use regex::Regex;
+
+use crate::error::NotmuchMoreError;
+use crate::parse;
+
+const INDEXED_PROPERTY: &str = "amail.atext-indexed";
+/// Bump to have everything re-indexed, e.g. on supporting more file types.
+const INDEX_VERSION: &str = "v2";
+const WORD_PROPERTY: &str = "amail.atext";
+/// Each word is a property, i.e. a row in the database, so this is kept low.
+const MAX_WORDS: usize = 1000;
+
+/// Concatenates the text of an XML document, with a space at least wherever
+/// a paragraph, cell, tab or break would separate words.
+fn xml_text(xml: &[u8]) -> String {
+    let mut reader = quick_xml::Reader::from_reader(xml);
+    let mut text = String::new();
+    let mut buf = vec![];
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Text(t)) => text.push_str(&t.unescape().unwrap_or_default()),
+            Ok(Event::End(e)) => {
+                if matches!(
+                    e.local_name().as_ref(),
+                    b"p" | b"h" | b"si" | b"tc" | b"row" | b"table-cell"
+                ) {
+                    text.push(' ');
+                }
+            }
+            Ok(Event::Empty(e)) => {
+                if matches!(
+                    e.local_name().as_ref(),
+                    b"tab" | b"br" | b"s" | b"line-break"
+                ) {
+                    text.push(' ');
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    text
+}
+
+/// The text of the XML members of a zipped (OOXML or ODF) document.
+fn zip_text(content: &[u8], members: fn(&str) -> bool) -> Option<String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(content)).ok()?;
+    let names = archive
+        .file_names()
+        .filter(|n| members(n))
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    let mut text = String::new();
+    for name in names {
+        let mut xml = vec![];
+        archive.by_name(&name).ok()?.read_to_end(&mut xml).ok()?;
+        text.push_str(&xml_text(&xml));
+        text.push(' ');
+    }
+    Some(text)
+}
+
+/// Extracts the text of PDF, OOXML, ODF, and plain text or CSV attachments,
+/// the latter in their `charset` if given.
+pub(crate) fn extract_text(
+    mimetype: &str,
+    filename: Option<&str>,
+    charset: Option<&str>,
+    content: &[u8],
+) -> Option<String> {
+    let ext = filename
+        .and_then(|f| f.rsplit_once('.'))
+        .map(|(_, e)| e.to_lowercase())
+        .unwrap_or_default();
+
+    match (mimetype, ext.as_str()) {
+        ("application/pdf", _) | (_, "pdf") => pdf_extract::extract_text_from_mem(content).ok(),
+        ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", _)
+        | (_, "docx") => zip_text(content, |n| {
+            n == "word/document.xml" || n.starts_with("word/header") || n.starts_with("word/footer")
+        }),
+        ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", _) | (_, "xlsx") => {
+            zip_text(content, |n| n == "xl/sharedStrings.xml")
+        }
+        ("application/vnd.openxmlformats-officedocument.presentationml.presentation", _)
+        | (_, "pptx") => zip_text(content, |n| {
+            n.starts_with("ppt/slides/slide") && n.ends_with(".xml")
+        }),
+        (m, _) if m.starts_with("application/vnd.oasis.opendocument.") => {
+            zip_text(content, |n| n == "content.xml")
+        }
+        (_, "odt" | "ods" | "odp") => zip_text(content, |n| n == "content.xml"),
+        ("text/plain" | "text/csv", _) | (_, "txt" | "csv") => {
+            Some(parse::decode(content, charset).0)
+        }
+        _ => None,
+    }
+}
+
+/// The distinct, lowercased words of `text`, as they're indexed: the first
+/// `MAX_WORDS` of them.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| (2..=40).contains(&w.chars().count()))
+        .map(str::to_lowercase)
+        .unique()
+        .take(MAX_WORDS)
+        .collect()
+}
+
+/// The text extracted from every attachment of the message in `path`,
+/// including those in TNEF (`winmail.dat`) attachments.
+fn attachments_text(path: impl AsRef<Path>) -> Result<String, NotmuchMoreError> {
+    let eml = fs::read(path)?;
+    let mail = mailparse::parse_mail(&eml)?;
+
+    let mut text = String::new();
+    let mut push = |t: Option<String>| {
+        if let Some(t) = t {
+            text.push_str(&t);
+            text.push(' ');
+        }
+    };
+    for (_, part) in parse::leaf_parts(&mail, "") {
+        let disposition = part.get_content_disposition();
+        if disposition.disposition != DispositionType::Attachment {
+            continue;
+        }
+
+        if parse::is_tnef(part) {
+            for p in parse::parse_tnef(&part.get_body_raw()?)? {
+                if p.disposition == DispositionType::Attachment {
+                    push(extract_text(
+                        &p.mimetype,
+                        p.filename.as_deref(),
+                        None,
+                        &p.content,
+                    ));
+                }
+            }
+            continue;
+        }
+
+        let filename = disposition
+            .params
+            .get("filename")
+            .or_else(|| part.ctype.params.get("name"));
+        push(extract_text(
+            &part.ctype.mimetype,
+            filename.map(String::as_str),
+            part.ctype.params.get("charset").map(String::as_str),
+            &part.get_body_raw()?,
+        ));
+    }
+    Ok(text)
+}
+
+/// Indexes the words in the attachments of up to `limit` messages that
+/// haven't been yet, as notmuch properties. Returns how many were indexed.
+pub fn index_attachments(db: &Database, limit: usize) -> Result<usize, NotmuchMoreError> {
+    let query = db.create_query(&format!(
+        "tag:attachment and not property:{INDEXED_PROPERTY}={INDEX_VERSION}"
+    ))?;
+    query.set_sort(notmuch::Sort::NewestFirst);
+
+    let mut indexed = 0;
+    for msg in query.search_messages()?.take(limit) {
+        println!("[TRACE] Indexing attachments of {}", msg.id());
+        // The extractors (notably PDF's) can panic on malformed files, which
+        // would otherwise leave this message to be retried, and panic, forever.
+        let path = msg.filename();
+        let text = panic::catch_unwind(|| attachments_text(path))
+            .unwrap_or_else(|_| Err(anyhow!("Panicked extracting text").into()))
+            .unwrap_or_else(|e| {
+                println!("[WARN] Failed to extract attachments of {}: {e}", msg.id());
+                String::new()
+            });
+
+        msg.freeze()?;
+        msg.remove_all_properties(Some(WORD_PROPERTY))?;
+        for word in words(&text) {
+            msg.add_property(WORD_PROPERTY, &word)?;
+        }
+        msg.remove_all_properties(Some(INDEXED_PROPERTY))?;
+        msg.add_property(INDEXED_PROPERTY, INDEX_VERSION)?;
+        msg.thaw()?;
+        indexed += 1;
+    }
+
+    Ok(indexed)
+}
+
+/// Rewrites `atext:word` (or `atext:"some words"`) terms to match the words
+/// indexed from attachments.
+pub(crate) fn rewrite_query(query: &str) -> String {
+    Regex::new(r#"\batext:(?:"([^"]*)"|(\S+))"#)
+        .unwrap()
+        .replace_all(query, |c: &Captures| {
+            let terms = words(c.get(1).or(c.get(2)).map_or("", |m| m.as_str()))
+                .iter()
+                .map(|w| format!("property:{WORD_PROPERTY}={w}"))
+                .collect::<Vec<_>>();
+            match terms.len() {
+                0 => format!("property:{WORD_PROPERTY}="),
+                1 => terms[0].clone(),
+                _ => format!("({})", terms.join(" and ")),
+            }
+        })
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn zipped(members: &[(&str, &str)]) -> Vec<u8> {
+        let mut buf = Cursor::new(vec![]);
+        let mut zip = zip::ZipWriter::new(&mut buf);
+        for (name, content) in members {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn docx_runs_joined() {
+        let docx = zipped(&[
+            (
+                "word/document.xml",
+                r#"<w:document xmlns:w="w"><w:body><w:p><w:r><w:t>Quar</w:t></w:r><w:r><w:t>terly</w:t></w:r></w:p><w:p><w:r><w:t>Report &amp; more</w:t></w:r></w:p></w:body></w:document>"#,
+            ),
+            ("word/styles.xml", "<w:styles>Ignored</w:styles>"),
+        ]);
+        let text = extract_text("application/octet-stream", Some("Q3.DOCX"), None, &docx).unwrap();
+
+        assert_eq!(words(&text), vec!["quarterly", "report", "more"]);
+    }
+
+    #[test]
+    fn odf_and_csv() {
+        let odt = zipped(&[(
+            "content.xml",
+            r#"<office:document-content><text:p>Hello<text:s/>world</text:p></office:document-content>"#,
+        )]);
+
+        assert_eq!(
+            words(
+                &extract_text("application/vnd.oasis.opendocument.text", None, None, &odt).unwrap()
+            ),
+            vec!["hello", "world"]
+        );
+        assert_eq!(
+            words(&extract_text("text/csv", None, None, b"Name,Total\nWidgets,42").unwrap()),
+            vec!["name", "total", "widgets", "42"]
+        );
+        assert_eq!(extract_text("image/png", Some("a.png"), None, b""), None);
+    }
+
+    #[test]
+    fn text_in_charset_and_tnef_indexed() {
+        use base64::Engine;
+        let mut eml = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nHi\r\n\
+            --b\r\nContent-Type: text/plain; charset=iso-8859-1\r\n\
+            Content-Disposition: attachment; filename=note.txt\r\n\r\nGr\xfc\xdfe\r\n"
+            .to_vec();
+        eml.extend(
+            format!(
+                "--b\r\nContent-Type: application/ms-tnef; name=winmail.dat\r\n\
+                Content-Disposition: attachment\r\n\
+                Content-Transfer-Encoding: base64\r\n\r\n{}\r\n--b--\r\n",
+                base64::engine::general_purpose::STANDARD.encode(parse::tnef_sample())
+            )
+            .bytes(),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("eml");
+        fs::write(&path, eml).unwrap();
+
+        assert_eq!(
+            words(&attachments_text(&path).unwrap()),
+            vec!["grüße", "sales", "up"]
+        );
+    }
+
+    #[test]
+    fn words_capped_in_order_seen() {
+        let text = (0..MAX_WORDS + 10)
+            .rev()
+            .map(|i| format!("w{i} W{i}"))
+            .join(" ");
+        let words = words(&text);
+
+        assert_eq!(words.len(), MAX_WORDS);
+        assert_eq!(words[0], format!("w{}", MAX_WORDS + 9));
+        assert_eq!(words[MAX_WORDS - 1], "w10");
+    }
+
+    #[test]
+    fn query_rewritten() {
+        assert_eq!(
+            rewrite_query("from:bob and atext:Invoice"),
+            "from:bob and property:amail.atext=invoice"
+        );
+        assert_eq!(
+            rewrite_query(r#"atext:"Q3 report" or tag:inbox"#),
+            "(property:amail.atext=q3 and property:amail.atext=report) or tag:inbox"
+        );
+        assert_eq!(rewrite_query("subject:atext"), "subject:atext");
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use mailparse::DispositionType;
+use mailparse::ParsedMail;
+use notmuch::Database;
+
+use crate::error::NotmuchMoreError;
+use crate::parse;
+
+fn read_eml(db: &Database, id: &str) -> Result<Vec<u8>, NotmuchMoreError> {
+    let msg = db
+        .find_message(id)?
+        .ok_or_else(|| anyhow!("Message {} not found", id))?;
+    Ok(fs::read(msg.filename())?)
+}
+
+/// The part's filename, stripped of any directories.
+fn filename(part: &ParsedMail) -> Option<String> {
+    part.get_content_disposition()
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .and_then(|f| sanitise_filename(f))
+}
+
+fn sanitise_filename(name: &str) -> Option<String> {
+    Some(
+        name.rsplit(['/', '\\'])
+            .next()
+            .unwrap_or("")
+            .trim_start_matches('.'),
+    )
+    .filter(|f| !f.trim().is_empty())
+    .map(Into::into)
+}
+
+/// The filename and decoded content of part `part_id`, which may have been
+/// decoded from a TNEF part.
+fn find_part(
+    mail: &ParsedMail,
+    id: &str,
+    part_id: &str,
+) -> Result<(Option<String>, Vec<u8>), NotmuchMoreError> {
+    if let Some(part) = parse::find_part(mail, part_id) {
+        return Ok((filename(part), part.get_body_raw()?));
+    }
+    let part = parse::tnef_part(mail, part_id)
+        .ok_or_else(|| anyhow!("Message {} has no part {}", id, part_id))?;
+    Ok((
+        part.filename.as_deref().and_then(sanitise_filename),
+        part.content,
+    ))
+}
+
+/// `name` in `dir`, numbered if need be so as not to clash with an existing
+/// file or one of `taken`.
+fn unique_path(dir: &Path, name: &str, taken: &HashSet<PathBuf>) -> PathBuf {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+
+    (0..)
+        .map(|n| match n {
+            0 => dir.join(name),
+            n => dir.join(format!("{stem} ({n}){ext}")),
+        })
+        .find(|p| !p.exists() && !taken.contains(p))
+        .unwrap()
+}
+
+/// The decoded content of part `part_id` of message `id`.
+pub fn read_part(db: &Database, id: &str, part_id: &str) -> Result<Vec<u8>, NotmuchMoreError> {
+    println!("Reading part {part_id} of {id}");
+    let eml = read_eml(db, id)?;
+    let mail = mailparse::parse_mail(&eml)?;
+    Ok(find_part(&mail, id, part_id)?.1)
+}
+
+pub fn save_part(
+    db: &Database,
+    id: &str,
+    part_id: &str,
+    path: &Path,
+) -> Result<(), NotmuchMoreError> {
+    println!("Saving part {part_id} of {id} to {}", path.display());
+    Ok(fs::write(path, read_part(db, id, part_id)?)?)
+}
+
+/// Saves part `part_id` of message `id` under its own name in a new temporary
+/// directory, e.g. to be opened in another application.
+pub fn save_part_temp(db: &Database, id: &str, part_id: &str) -> Result<PathBuf, NotmuchMoreError> {
+    let eml = read_eml(db, id)?;
+    let mail = mailparse::parse_mail(&eml)?;
+    let (name, content) = find_part(&mail, id, part_id)?;
+
+    let dir = tempfile::Builder::new()
+        .prefix("amail-")
+        .tempdir()?
+        .into_path();
+    let path = dir.join(name.unwrap_or_else(|| format!("part-{part_id}")));
+    println!("Saving part {part_id} of {id} to {}", path.display());
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// Saves every attachment of message `id` into `dir`, returning their paths.
+pub fn save_attachments(
+    db: &Database,
+    id: &str,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, NotmuchMoreError> {
+    println!("Saving attachments of {id} to {}", dir.display());
+    let eml = read_eml(db, id)?;
+    let mail = mailparse::parse_mail(&eml)?;
+
+    let mut saved = HashSet::new();
+    let mut paths = vec![];
+    for (part_id, part) in parse::leaf_parts(&mail, "") {
+        if part.get_content_disposition().disposition != DispositionType::Attachment {
+            continue;
+        }
+
+        // Rather than winmail.dat itself, what it contains.
+        let files = match parse::is_tnef(part) {
+            true => parse::parse_tnef(&part.get_body_raw()?)?
+                .into_iter()
+                .filter(|p| p.disposition == DispositionType::Attachment)
+                .map(|p| (p.filename.as_deref().and_then(sanitise_filename), p.content))
+                .collect(),
+            false => vec![(filename(part), part.get_body_raw()?)],
+        };
+        for (name, content) in files {
+            let name = name.unwrap_or_else(|| format!("part-{part_id}"));
+            let path = unique_path(dir, &name, &saved);
+            fs::write(&path, content)?;
+            saved.insert(path.clone());
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filename_without_directories() {
+        let mail = mailparse::parse_mail(
+            b"Content-Type: text/plain\r\nContent-Disposition: attachment; filename=\"../../.bashrc\"\r\n\r\nx",
+        )
+        .unwrap();
+
+        assert_eq!(filename(&mail).as_deref(), Some("bashrc"));
+    }
+
+    #[test]
+    fn unique_paths_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "").unwrap();
+        let taken = HashSet::from([dir.path().join("a (1).txt")]);
+
+        assert_eq!(
+            unique_path(dir.path(), "a.txt", &taken),
+            dir.path().join("a (2).txt")
+        );
+        assert_eq!(unique_path(dir.path(), "b", &taken), dir.path().join("b"));
+    }
+}
@@ -1,9 +1,12 @@
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::fs;
+use std::io::Write;
+use std::path::Path;
 
 use anyhow::anyhow;
-use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64_STANDARD};
+use chrono::DateTime;
+use chrono::FixedOffset;
 use chrono::Local;
 use chrono::TimeZone;
 use chrono::Utc;
@@ -15,16 +18,29 @@ use serde::Serialize;
 
 use crate::NotmuchMoreError;
 use crate::parse;
+use crate::templates::Placeholders;
+use crate::templates::Signature;
+use crate::templates::Template;
+use mime::Part;
+use parse::Attendee;
+use parse::CalEvent;
+use parse::CalTime;
+use parse::Calendar;
 use parse::EmlAddr;
 use parse::EmlBody;
 use parse::EmlMeta;
 use parse::Mailbox;
+use parse::PartStat;
 use parse::Rfc5322Fields;
 
+mod mime;
+
 #[derive(Clone, Debug, Default, Serialize)]
 pub struct ReplyTemplate {
     pub meta: EmlMeta,
     pub body: String,
+    /// Whether the template or signature applied is written in Markdown.
+    pub markdown: bool,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -33,16 +49,49 @@ pub struct Attachment {
     pub path: String,
 }
 
-fn format_part(
-    boundary: &str,
-    ctype: &str,
-    ctencoding: &str,
-    disposition: &str,
-    content: &str,
-) -> String {
-    format!(
-        "--{boundary}\r\nContent-Type: {ctype}\r\nContent-Transfer-Encoding: {ctencoding}\r\nContent-Disposition: {disposition}\r\n\r\n{content}\r\n\r\n",
-    )
+/// How the plain text body is laid out on the wire.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub enum BodyFormat {
+    /// RFC 3676 `format=flowed`: long lines are soft-broken, such that capable
+    /// clients rejoin them and others still see lines of at most 78ch.
+    #[default]
+    Flowed,
+    /// Sent as written; long lines are quoted-printable encoded, not wrapped.
+    Fixed,
+    /// Hard-wrapped at 78ch.
+    Wrapped,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ComposeOptions {
+    pub body_format: BodyFormat,
+    /// Treat the body as Markdown, sending it rendered as HTML alongside the
+    /// original text.
+    pub markdown: bool,
+    /// Invite the recipients to a meeting, described by the subject and body.
+    pub invite: Option<Invite>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Invite {
+    /// Set to update a previous invitation, otherwise generated.
+    #[serde(default)]
+    pub uid: Option<String>,
+    #[serde(default)]
+    pub sequence: u32,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub all_day: bool,
+    #[serde(default)]
+    pub location: Option<String>,
+}
+
+fn crlf(body: &str) -> String {
+    body.split('\n')
+        .map(|l| l.strip_suffix('\r').unwrap_or(l))
+        .join("\r\n")
 }
 
 fn format_body(body: &str) -> String {
@@ -53,53 +102,412 @@ fn format_body(body: &str) -> String {
         .into()
 }
 
-fn format_attachment(content: &str) -> String {
-    Regex::new(r"(^|[^\r])\n")
-        .unwrap()
-        // MUST limit to 998ch; since this is not human-readable content
-        // there's no point further limiting to 78ch for readability.
-        .replace_all(&textwrap::fill(content, 998), "$1\r\n")
-        .into()
+fn format_body_flowed(body: &str) -> String {
+    body.split('\n')
+        .map(|l| l.strip_suffix('\r').unwrap_or(l))
+        .flat_map(flow_line)
+        .join("\r\n")
+}
+
+/// Soft-breaks a single hard line per RFC 3676, preserving its quote depth.
+fn flow_line(line: &str) -> Vec<String> {
+    // The trailing space of a signature separator isn't a soft break.
+    if line == "-- " {
+        return vec![line.into()];
+    }
+
+    let depth = line.chars().take_while(|&c| c == '>').count();
+    let (quotes, content) = line.split_at(depth);
+    // Trailing spaces would otherwise be read as soft breaks.
+    let content = content.trim_end_matches(' ');
+    let (prefix, content) = match depth {
+        0 => (String::new(), content),
+        _ => (
+            format!("{quotes} "),
+            content.strip_prefix(' ').unwrap_or(content),
+        ),
+    };
+
+    if content.is_empty() {
+        return vec![quotes.into()];
+    }
+
+    // Unquoted lines starting with a space, quote or "From " are
+    // space-stuffed (RFC 3676 §4.4), which counts towards their width.
+    let stuffed = |l: &str| {
+        depth == 0 && (l.starts_with(' ') || l.starts_with('>') || l.starts_with("From "))
+    };
+
+    let mut lines: Vec<String> = vec![];
+    let mut current = String::new();
+    for word in Regex::new(r"\s*\S+\s*").unwrap().find_iter(content) {
+        let line = format!("{current}{}", word.as_str().trim_end());
+        let width = prefix.len() + usize::from(stuffed(&line)) + line.chars().count();
+        if !current.is_empty() && width > 78 {
+            lines.push(std::mem::take(&mut current));
+        }
+        current.push_str(word.as_str());
+    }
+    lines.push(current);
+
+    lines
+        .into_iter()
+        .map(|l| {
+            let stuffing = match stuffed(&l) {
+                true => " ",
+                false => "",
+            };
+            format!("{prefix}{stuffing}{l}")
+        })
+        .collect()
+}
+
+fn format_text_part(ctype: &str, content: String) -> Part {
+    // Rather than destructively wrapping whatever couldn't be flowed (or
+    // wasn't meant to be), encode it such that it's restored on receipt.
+    if content.split("\r\n").any(|l| l.chars().count() > 78) {
+        Part::new(
+            ctype,
+            "quoted-printable",
+            quoted_printable::encode_to_str(&content),
+        )
+    } else {
+        Part::new(ctype, "8bit", content)
+    }
+}
+
+fn format_body_part(body: &str, format: BodyFormat) -> Part {
+    match format {
+        BodyFormat::Flowed => format_text_part(
+            "text/plain; charset=utf-8; format=flowed",
+            format_body_flowed(body),
+        ),
+        BodyFormat::Fixed => format_text_part("text/plain; charset=utf-8", crlf(body)),
+        BodyFormat::Wrapped => format_text_part("text/plain; charset=utf-8", format_body(body)),
+    }
+}
+
+fn format_markdown_part(body: &str) -> Part {
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(
+        &mut html,
+        pulldown_cmark::Parser::new_ext(
+            body,
+            pulldown_cmark::Options::ENABLE_TABLES | pulldown_cmark::Options::ENABLE_STRIKETHROUGH,
+        ),
+    );
+
+    format_text_part("text/html; charset=utf-8", crlf(&ammonia::clean(&html)))
+}
+
+fn format_attachment_part(attachment: &Attachment) -> Result<Part, NotmuchMoreError> {
+    let metadata = fs::metadata(&attachment.path)?;
+    let mut disposition = vec![
+        "attachment".into(),
+        mime::format_param("filename", &attachment.name),
+        mime::format_param("size", &metadata.len().to_string()),
+    ];
+    if let Ok(modified) = metadata.modified() {
+        disposition.push(mime::format_param(
+            "modification-date",
+            &DateTime::<Local>::from(modified).to_rfc2822(),
+        ));
+    }
+
+    Ok(Part::base64_file(
+        &format!(
+            "{}; {}",
+            mime_guess::from_path(&attachment.path)
+                .first_or_octet_stream()
+                .essence_str(),
+            mime::format_legacy_param("name", &attachment.name),
+        ),
+        Path::new(&attachment.path),
+    )
+    .header("Content-Disposition", &disposition.join(";\r\n ")))
+}
+
+/// A `REQUEST` for the To (required) and Cc (optional) recipients to attend.
+fn format_invite_part(
+    meta: &EmlMeta,
+    body: &str,
+    invite: &Invite,
+) -> Result<Part, NotmuchMoreError> {
+    let organizer = meta
+        .from
+        .first()
+        .ok_or_else(|| anyhow!("Invitation must be from an organiser"))?;
+    let attendee = |m: &Mailbox, role: &str| Attendee {
+        address: m.address.clone(),
+        name: Some(m.name.clone()).filter(|n| !n.is_empty()),
+        partstat: Some("NEEDS-ACTION".into()),
+        role: Some(role.into()),
+        rsvp: true,
+    };
+    let mailboxes = |addrs: &Option<Vec<EmlAddr>>| {
+        addrs
+            .iter()
+            .flatten()
+            .flat_map(|a| match a {
+                EmlAddr::Single(m) => vec![m.clone()],
+                EmlAddr::Group { members, .. } => members.clone(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let time = |datetime: &DateTime<FixedOffset>| CalTime {
+        datetime: *datetime,
+        all_day: invite.all_day,
+        tzid: None,
+        tz_unrecognised: false,
+    };
+    let calendar = Calendar {
+        method: Some("REQUEST".into()),
+        events: vec![CalEvent {
+            uid: invite.uid.clone().unwrap_or_else(|| {
+                parse::generate_message_id(&organizer.address)
+                    .trim_matches(['<', '>'])
+                    .into()
+            }),
+            sequence: invite.sequence,
+            summary: meta.subject.clone(),
+            description: Some(body.trim().to_string()).filter(|b| !b.is_empty()),
+            location: invite.location.clone(),
+            status: Some("CONFIRMED".into()),
+            organizer: Some(Attendee {
+                partstat: None,
+                role: None,
+                rsvp: false,
+                ..attendee(organizer, "CHAIR")
+            }),
+            attendees: mailboxes(&meta.to)
+                .iter()
+                .map(|m| attendee(m, "REQ-PARTICIPANT"))
+                .chain(
+                    mailboxes(&meta.cc)
+                        .iter()
+                        .map(|m| attendee(m, "OPT-PARTICIPANT")),
+                )
+                .collect(),
+            start: Some(time(&invite.start)),
+            end: Some(time(&invite.end)),
+            ..Default::default()
+        }],
+    };
+
+    Ok(Part::new(
+        "text/calendar; charset=utf-8; method=REQUEST",
+        "8bit",
+        calendar.format(&Utc::now()),
+    ))
+}
+
+/// Writes the message out as it's assembled, so that attachments are streamed
+/// from disk rather than held in memory.
+pub fn write_message(
+    meta: &EmlMeta,
+    body: String,
+    attachments: Vec<Attachment>,
+    options: &ComposeOptions,
+    w: &mut dyn Write,
+) -> Result<(), NotmuchMoreError> {
+    let mut alternatives =
+        vec![format_body_part(&body, options.body_format).header("Content-Disposition", "inline")];
+    if options.markdown {
+        alternatives.push(format_markdown_part(&body).header("Content-Disposition", "inline"));
+    }
+    if let Some(invite) = &options.invite {
+        alternatives.push(format_invite_part(meta, &body, invite)?);
+    }
+    let mut parts: Vec<Part> = vec![Part::multipart("alternative", alternatives)];
+
+    for attachment in attachments {
+        parts.push(format_attachment_part(&attachment)?);
+    }
+
+    write!(
+        w,
+        "{}\r\nMIME-Version: 1.0\r\n",
+        Rfc5322Fields::from(meta).format_fields()
+    )?;
+    Ok(Part::multipart("mixed", parts).write_to(w)?)
 }
 
 pub fn format_message(
     meta: &EmlMeta,
     body: String,
     attachments: Vec<Attachment>,
+    options: &ComposeOptions,
 ) -> Result<String, NotmuchMoreError> {
-    let boundary = "amail-boundary";
-    let mut parts: Vec<String> = vec![format_part(
-        boundary,
-        "text/plain; charset=utf-8",
-        "8bit",
-        "inline",
-        &format_body(&body),
-    )];
+    let mut eml = vec![];
+    write_message(meta, body, attachments, options, &mut eml)?;
+    Ok(String::from_utf8(eml).map_err(|e| e.utf8_error())?)
+}
 
-    for attachment in attachments {
-        parts.push(format_part(
-            boundary,
-            mime_guess::from_path(&attachment.path)
-                .first_or_octet_stream()
-                .essence_str(),
-            "base64",
-            &format!("attachment; filename={}", attachment.name),
-            &format_attachment(&BASE64_STANDARD.encode(&fs::read(&attachment.path)?)),
-        ));
+/// An iTIP REPLY to a meeting invitation, giving our participation status.
+#[derive(Clone, Debug)]
+pub struct CalendarReply {
+    pub meta: EmlMeta,
+    pub calendar: Calendar,
+}
+
+impl CalendarReply {
+    pub fn write_to(&self, w: &mut dyn Write) -> Result<(), NotmuchMoreError> {
+        let (event, attendee) = self
+            .calendar
+            .events
+            .first()
+            .and_then(|e| Some((e, e.attendees.first()?)))
+            .ok_or_else(|| anyhow!("Calendar reply has no event or attendee"))?;
+        let text = format!(
+            "{} has {} the invitation to {}.",
+            attendee.name.as_deref().unwrap_or(&attendee.address),
+            attendee.partstat.as_deref().unwrap_or("").to_lowercase(),
+            event.summary.as_deref().unwrap_or("the event"),
+        );
+
+        write!(
+            w,
+            "{}\r\nMIME-Version: 1.0\r\n",
+            Rfc5322Fields::from(&self.meta).format_fields()
+        )?;
+        Ok(Part::multipart(
+            "alternative",
+            vec![
+                format_text_part("text/plain; charset=utf-8", text),
+                Part::new(
+                    "text/calendar; charset=utf-8; method=REPLY",
+                    "8bit",
+                    self.calendar.format(&Utc::now()),
+                ),
+            ],
+        )
+        .write_to(w)?)
     }
+}
+
+/// Builds a reply to the invitation in message `id` from whichever of the
+/// invited attendees it was addressed to, to be sent to the organiser.
+pub fn calendar_reply(
+    db: &Database,
+    id: String,
+    partstat: PartStat,
+) -> Result<CalendarReply, NotmuchMoreError> {
+    println!("[TRACE] replying to invitation");
+    let (invite_meta, msg) = parse::parse_eml(db, id)?;
+    let mut event = parse::calendar(&msg)
+        .filter(|c| c.method.as_deref() == Some("REQUEST"))
+        .and_then(|c| c.events.first())
+        .cloned()
+        .ok_or_else(|| anyhow!("Message {} is not an invitation", invite_meta.id))?;
+
+    let recipients = [&invite_meta.to, &invite_meta.cc]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .flat_map(|a| match a {
+            EmlAddr::Single(m) => vec![m.clone()],
+            EmlAddr::Group { members, .. } => members.clone(),
+        })
+        .chain(invite_meta.received_by.clone())
+        .collect::<Vec<_>>();
+    let mut attendee = event
+        .attendees
+        .iter()
+        .find(|a| {
+            recipients
+                .iter()
+                .any(|r| r.address.eq_ignore_ascii_case(&a.address))
+        })
+        .cloned()
+        .ok_or_else(|| anyhow!("Not invited as any recipient of {}", invite_meta.id))?;
+    let organizer = event
+        .organizer
+        .clone()
+        .ok_or_else(|| anyhow!("Invitation {} has no organiser", invite_meta.id))?;
+
+    attendee.partstat = Some(partstat.as_str().into());
+    attendee.rsvp = false;
+    event.attendees = vec![attendee.clone()];
+    // Only what identifies the event (and our response to it) is needed.
+    event.description = None;
+    event.location = None;
+    event.status = None;
 
-    Ok(Rfc5322Fields::from(meta).format_message(&parts.iter().join(""), boundary))
+    Ok(CalendarReply {
+        meta: EmlMeta {
+            from: vec![Mailbox {
+                name: attendee.name.clone().unwrap_or_default(),
+                address: attendee.address,
+            }],
+            in_reply_to: Some(format!("<{}>", invite_meta.id)),
+            references: Some(format!(
+                "{} <{}>",
+                invite_meta.references.as_deref().unwrap_or(""),
+                invite_meta.id
+            )),
+            subject: Some(format!(
+                "{}: {}",
+                match partstat {
+                    PartStat::Accepted => "Accepted",
+                    PartStat::Tentative => "Tentative",
+                    PartStat::Declined => "Declined",
+                },
+                event.summary.as_deref().unwrap_or("")
+            )),
+            timestamp: Local::now().timestamp(),
+            to: Some(vec![EmlAddr::Single(Mailbox {
+                name: organizer.name.unwrap_or_default(),
+                address: organizer.address,
+            })]),
+            ..Default::default()
+        },
+        calendar: Calendar {
+            method: Some("REPLY".into()),
+            events: vec![event],
+        },
+    })
 }
 
-fn template_body(meta: &EmlMeta, body: &EmlBody) -> String {
+/// Drops everything from the (last) signature separator onwards.
+fn strip_signature(text: &str) -> &str {
+    let sig = text.match_indices('\n').map(|(i, _)| i + 1).rfind(|&i| {
+        // Trailing whitespace is often stripped in transit.
+        text[i..].split('\n').next().map(str::trim_end) == Some("--")
+    });
+
+    match sig {
+        Some(i) => text[..i].trim_end(),
+        None => text.trim_end(),
+    }
+}
+
+/// Quotes each line, nesting lines that were already quoted.
+fn quote(text: &str) -> String {
+    text.split('\n')
+        .map(|l| l.strip_suffix('\r').unwrap_or(l))
+        .map(|l| match (l.starts_with('>'), l.is_empty()) {
+            (true, _) => format!(">{l}"),
+            (false, true) => ">".into(),
+            (false, false) => format!("> {l}"),
+        })
+        .join("\r\n")
+}
+
+fn template_body<Tz: TimeZone>(meta: &EmlMeta, body: &EmlBody, tz: &Tz) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
     format!(
         "\r\n\r\nOn {}, {} wrote:\r\n{}",
-        Utc.timestamp_opt(meta.timestamp, 0).unwrap().to_rfc2822(),
+        tz.timestamp_opt(meta.timestamp, 0).unwrap().to_rfc2822(),
         meta.from
             .last()
             .map(String::from)
             .unwrap_or_else(|| "".into()),
-        parse::plaintext(body).unwrap_or_else(|| "[no plaintext]".into()),
+        quote(strip_signature(
+            &parse::text(body).unwrap_or_else(|| "[no text]".into())
+        )),
     )
 }
 
@@ -115,13 +523,6 @@ pub fn template_reply(db: &Database, id: String) -> Result<ReplyTemplate, Notmuc
     }
 
     reply_fields.date(&Local::now());
-    reply_fields.message_id(
-        &reply_fields.format_message_id_for_destination(
-            &reply_to_meta
-                .resolve_reply_to()
-                .map_err(|e| anyhow!("Failed to parse: {e}"))?,
-        ),
-    );
     reply_fields.in_reply_to(&format!("<{}>", &reply_to_meta.id));
     reply_fields.references(&format!(
         "{} <{}>",
@@ -155,13 +556,124 @@ pub fn template_reply(db: &Database, id: String) -> Result<ReplyTemplate, Notmuc
         meta: reply_fields
             .try_into()
             .map_err(|e| anyhow!("Failed to parse: {e}"))?,
-        body: template_body(&reply_to_meta, &msg),
+        body: template_body(&reply_to_meta, &msg, &reply_to_meta.sender_offset()),
+        ..Default::default()
+    })
+}
+
+pub fn template_forward(db: &Database, id: String) -> Result<ReplyTemplate, NotmuchMoreError> {
+    println!("[TRACE] templating forward");
+    let (forward_meta, msg) = parse::parse_eml(db, id)?;
+
+    let mut fields = Rfc5322Fields::new();
+    let subject = forward_meta.subject.as_deref().unwrap_or("");
+    fields.subject(&match subject.to_lowercase().starts_with("fwd:") {
+        true => subject.into(),
+        false => format!("Fwd: {subject}"),
+    });
+    fields.date(&Local::now());
+    if let Some(original_to) = &forward_meta.to {
+        fields.from_addr(
+            &original_to
+                .iter()
+                .map(Mailbox::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| anyhow!("Failed to parse: {e}"))?,
+        );
+    }
+
+    let mut original_headers = vec![
+        format!(
+            "From: {}",
+            forward_meta.from.iter().map(String::from).join(", ")
+        ),
+        format!(
+            "Date: {}",
+            forward_meta
+                .sender_offset()
+                .timestamp_opt(forward_meta.timestamp, 0)
+                .unwrap()
+                .to_rfc2822()
+        ),
+        format!("Subject: {subject}"),
+    ];
+    if let Some(to) = &forward_meta.to {
+        original_headers.push(format!("To: {}", to.iter().map(String::from).join(", ")));
+    }
+
+    Ok(ReplyTemplate {
+        meta: fields
+            .try_into()
+            .map_err(|e| anyhow!("Failed to parse: {e}"))?,
+        body: format!(
+            "\r\n\r\n---------- Forwarded message ----------\r\n{}\r\n\r\n{}",
+            original_headers.join("\r\n"),
+            parse::text(&msg).unwrap_or_else(|| "[no text]".into()),
+        ),
+        ..Default::default()
     })
 }
 
+pub fn template_new(from: Vec<Mailbox>) -> ReplyTemplate {
+    ReplyTemplate {
+        meta: EmlMeta {
+            from,
+            timestamp: Local::now().timestamp(),
+            to: Some(vec![]),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Applies a template and signature to a draft from one of the `template_*`
+/// functions, with placeholders taken from the draft and from `original`, the
+/// message being replied to or forwarded (if any).
+pub fn apply_template(
+    draft: &mut ReplyTemplate,
+    template: Option<&Template>,
+    signature: Option<&Signature>,
+    original: Option<&EmlMeta>,
+) {
+    let placeholders = Placeholders {
+        recipient_name: draft
+            .meta
+            .to
+            .iter()
+            .flatten()
+            .find_map(|a| Mailbox::try_from(a).ok())
+            .map(|m| match m.name.is_empty() {
+                true => m.address,
+                false => m.name,
+            })
+            .unwrap_or_default(),
+        original_subject: original.and_then(|o| o.subject.clone()).unwrap_or_default(),
+        date: Local::now().format("%-d %B %Y").to_string(),
+    };
+
+    let mut content = String::new();
+    if let Some(template) = template {
+        if let Some(subject) = &template.subject {
+            draft.meta.subject = Some(placeholders.render(subject));
+        }
+        content.push_str(&placeholders.render(&template.body));
+        draft.markdown |= template.markdown;
+    }
+    content.push_str(&draft.body);
+    // Below any quoted text, which would otherwise be taken as part of it;
+    // and only making Markdown of a message that's all its own.
+    if let Some(signature) = signature {
+        draft.markdown |= signature.markdown && draft.body.trim().is_empty();
+        content.push_str(&format!("\r\n\r\n-- \r\n{}", signature.content));
+    }
+
+    draft.body = content;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64_STANDARD};
     use parse::Mailbox;
     use std::default::Default;
 
@@ -217,6 +729,205 @@ mod tests {
         assert_eq!(format_body("\n"), "\r\n")
     }
 
+    #[test]
+    fn flowed_body_short_lines() {
+        assert_eq!(
+            format_body_flowed("hi there\n\nbye  "),
+            "hi there\r\n\r\nbye"
+        );
+    }
+
+    #[test]
+    fn flowed_body_soft_breaks() {
+        let body = format!("{} word word word.", ".".repeat(75));
+        assert_eq!(
+            format_body_flowed(&body),
+            format!("{} \r\nword word word.", ".".repeat(75))
+        )
+    }
+
+    #[test]
+    fn flowed_body_long_word_unbroken() {
+        let url = format!("https://example.com/{}", "a".repeat(100));
+        assert_eq!(
+            format_body_flowed(&format!("see {url}")),
+            format!("see \r\n{url}")
+        )
+    }
+
+    #[test]
+    fn flowed_body_space_stuffing() {
+        assert_eq!(
+            format_body_flowed("  indented\nFrom here\n-- \nsig"),
+            "   indented\r\n From here\r\n-- \r\nsig",
+        )
+    }
+
+    #[test]
+    fn flowed_body_stuffing_within_width() {
+        // 78 columns unstuffed, so 79 stuffed.
+        let body = format!("From {} end", "x".repeat(69));
+        assert_eq!(
+            format_body_flowed(&body),
+            format!(" From {} \r\nend", "x".repeat(69))
+        )
+    }
+
+    #[test]
+    fn flowed_body_quoted() {
+        let body = format!(">> {} quoted", "q".repeat(74));
+        assert_eq!(
+            format_body_flowed(&body),
+            format!(">> {} \r\n>> quoted", "q".repeat(74))
+        )
+    }
+
+    #[test]
+    fn fixed_body_long_line_quoted_printable() {
+        let body = format!("{}\nshort", "x".repeat(100));
+        let part = format_body_part(&body, BodyFormat::Fixed).render();
+
+        assert!(part.contains("Content-Transfer-Encoding: quoted-printable\r\n"));
+        assert_eq!(
+            quoted_printable::decode(
+                part.split_once("\r\n\r\n").unwrap().1,
+                quoted_printable::ParseMode::Strict
+            )
+            .unwrap(),
+            format!("{}\r\nshort", "x".repeat(100)).as_bytes(),
+        )
+    }
+
+    #[test]
+    fn markdown_rendered_and_sanitised() {
+        let part = format_markdown_part("# Hi\n\n*there* <script>alert(1)</script>").render();
+
+        assert!(part.starts_with("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(part.contains("<h1>Hi</h1>\r\n<p><em>there</em> </p>"));
+        assert!(!part.contains("script"));
+    }
+
+    #[test]
+    fn markdown_message_alternative() {
+        let meta = EmlMeta {
+            from: vec![Mailbox {
+                name: "Enid Blyton".into(),
+                address: "enid@blyt.on".into(),
+            }],
+            ..Default::default()
+        };
+        let options = ComposeOptions {
+            markdown: true,
+            ..Default::default()
+        };
+
+        let eml = format_message(&meta, "**Hello**".into(), vec![], &options).unwrap();
+
+        assert!(eml.contains("\r\nContent-Type: multipart/alternative; boundary="));
+        assert!(eml.contains("format=flowed\r\nContent-Transfer-Encoding: 8bit\r\nContent-Disposition: inline\r\n\r\n**Hello**\r\n"));
+        assert!(eml.contains("<p><strong>Hello</strong></p>"));
+        assert!(!eml.contains("multipart/mixed"));
+    }
+
+    #[test]
+    fn attachment_disposition() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.txt");
+        fs::write(&path, "hello").unwrap();
+        let attachment = Attachment {
+            name: "Q3 report; final ✓.txt".into(),
+            path: path.display().to_string(),
+        };
+
+        let rendered = format_attachment_part(&attachment).unwrap().render();
+        let parsed = mailparse::parse_mail(rendered.as_bytes()).unwrap();
+        let disposition = parsed.get_content_disposition();
+
+        assert_eq!(
+            disposition.disposition,
+            mailparse::DispositionType::Attachment
+        );
+        assert_eq!(disposition.params.get("filename"), Some(&attachment.name));
+        assert_eq!(disposition.params.get("size"), Some(&"5".into()));
+        assert!(disposition.params.contains_key("modification-date"));
+        assert!(rendered.starts_with(&format!(
+            "Content-Type: text/plain; name=\"=?utf-8?B?{}?=\"\r\n",
+            BASE64_STANDARD.encode(&attachment.name)
+        )));
+        assert_eq!(parsed.get_body().unwrap(), "hello");
+    }
+
+    #[test]
+    fn message_without_attachments_not_multipart() {
+        let meta = EmlMeta {
+            from: vec![Mailbox {
+                name: "Enid Blyton".into(),
+                address: "enid@blyt.on".into(),
+            }],
+            ..Default::default()
+        };
+
+        let eml = format_message(&meta, "Hello".into(), vec![], &Default::default()).unwrap();
+
+        assert!(!eml.contains("multipart"));
+        assert!(eml.contains(
+            "\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8; format=flowed\r\n"
+        ));
+        assert!(eml.ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    fn template_and_signature_applied() {
+        let mut draft = ReplyTemplate {
+            meta: EmlMeta {
+                subject: Some("Re: Budget".into()),
+                to: Some(vec![EmlAddr::Single(Mailbox {
+                    name: "Lisa Cuddy".into(),
+                    address: "ceo@pph.com".into(),
+                })]),
+                ..Default::default()
+            },
+            body: "\r\n\r\nOn ..., Lisa wrote:\r\n> no".into(),
+            ..Default::default()
+        };
+        let original = EmlMeta {
+            subject: Some("Budget".into()),
+            ..Default::default()
+        };
+        let template = Template {
+            name: "decline".into(),
+            body: "Dear {{recipient_name}},\r\n\r\nNo to {{original_subject}}.".into(),
+            ..Default::default()
+        };
+        let signature = Signature {
+            content: "*Greg*".into(),
+            markdown: true,
+        };
+
+        apply_template(
+            &mut draft,
+            Some(&template),
+            Some(&signature),
+            Some(&original),
+        );
+
+        assert_eq!(
+            draft.body,
+            "Dear Lisa Cuddy,\r\n\r\nNo to Budget.\r\n\r\nOn ..., Lisa wrote:\r\n> no\r\n\r\n-- \r\n*Greg*",
+        );
+        assert_eq!(draft.meta.subject.as_deref(), Some("Re: Budget"));
+        assert!(!draft.markdown);
+        assert_eq!(
+            strip_signature(&draft.body),
+            "Dear Lisa Cuddy,\r\n\r\nNo to Budget.\r\n\r\nOn ..., Lisa wrote:\r\n> no"
+        );
+
+        let mut new = template_new(vec![]);
+        apply_template(&mut new, None, Some(&signature), None);
+        assert_eq!(new.body, "\r\n\r\n-- \r\n*Greg*");
+        assert!(new.markdown);
+    }
+
     #[test]
     fn simple_body_template() {
         let meta = EmlMeta {
@@ -234,8 +945,155 @@ mod tests {
         };
 
         assert_eq!(
-            template_body(&meta, &body),
-            "\r\n\r\nOn Fri, 13 Feb 2009 23:31:30 +0000, \"Enid Blyton\" <enid@blyt.on> wrote:\r\nFive Write Some Rust",
+            template_body(&meta, &body, &Utc),
+            "\r\n\r\nOn Fri, 13 Feb 2009 23:31:30 +0000, \"Enid Blyton\" <enid@blyt.on> wrote:\r\n> Five Write Some Rust",
+        );
+    }
+
+    #[test]
+    fn body_template_in_timezone() {
+        let meta = EmlMeta {
+            timestamp: 1234567890,
+            ..Default::default()
+        };
+        let body = EmlBody {
+            content: "Hi".into(),
+            mimetype: "text/plain".into(),
+            ..Default::default()
+        };
+
+        assert!(
+            template_body(&meta, &body, &FixedOffset::west_opt(5 * 3600).unwrap())
+                .starts_with("\r\n\r\nOn Fri, 13 Feb 2009 18:31:30 -0500, ")
+        );
+    }
+
+    #[test]
+    fn body_template_quotes_nested_and_strips_signature() {
+        let meta = EmlMeta::default();
+        let body = EmlBody {
+            content: "Sure.\r\n\r\n> Lunch?\r\n>> Hungry\r\n\r\n-- \r\nEnid\r\n".into(),
+            mimetype: "text/plain".into(),
+            ..Default::default()
+        };
+
+        assert!(
+            template_body(&meta, &body, &Utc)
+                .ends_with(" wrote:\r\n> Sure.\r\n>\r\n>> Lunch?\r\n>>> Hungry")
+        );
+    }
+
+    #[test]
+    fn body_template_html_fallback() {
+        let meta = EmlMeta::default();
+        let body = EmlBody {
+            content: "<p>Hello</p><p>World</p>".into(),
+            is_cleaned_html: true,
+            mimetype: "text/html".into(),
+            ..Default::default()
+        };
+
+        assert!(template_body(&meta, &body, &Utc).ends_with(" wrote:\r\n> Hello\r\n>\r\n> World"));
+    }
+
+    #[test]
+    fn calendar_reply_message() {
+        let mut calendar = parse::parse_calendar(
+            "BEGIN:VCALENDAR\r\nMETHOD:REPLY\r\nBEGIN:VEVENT\r\nUID:1@example.com\r\nSUMMARY:Lunch\r\nDTSTART:20260701T120000Z\r\nORGANIZER:mailto:org@example.com\r\nATTENDEE;PARTSTAT=ACCEPTED;CN=Me:mailto:me@example.org\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+        )
+        .unwrap();
+        calendar.method = Some("REPLY".into());
+        let reply = CalendarReply {
+            meta: EmlMeta {
+                from: vec![Mailbox {
+                    name: "Me".into(),
+                    address: "me@example.org".into(),
+                }],
+                subject: Some("Accepted: Lunch".into()),
+                to: Some(vec![]),
+                ..Default::default()
+            },
+            calendar,
+        };
+
+        let mut eml = vec![];
+        reply.write_to(&mut eml).unwrap();
+        let parsed = mailparse::parse_mail(&eml).unwrap();
+
+        assert_eq!(parsed.ctype.mimetype, "multipart/alternative");
+        assert_eq!(
+            parsed.subparts[0].get_body().unwrap(),
+            "Me has accepted the invitation to Lunch."
+        );
+        assert_eq!(
+            parsed.subparts[1].ctype.params.get("method").unwrap(),
+            "REPLY"
+        );
+        let sent = parse::parse_calendar(&parsed.subparts[1].get_body().unwrap()).unwrap();
+        assert_eq!(sent.events[0].uid, "1@example.com");
+        assert_eq!(
+            sent.events[0].attendees[0].partstat.as_deref(),
+            Some("ACCEPTED")
+        );
+
+        let mut empty = reply.clone();
+        empty.calendar.events[0].attendees.clear();
+        assert!(empty.write_to(&mut vec![]).is_err());
+    }
+
+    #[test]
+    fn invite_alongside_body() {
+        let mbox = |name: &str, address: &str| Mailbox {
+            name: name.into(),
+            address: address.into(),
+        };
+        let meta = EmlMeta {
+            from: vec![mbox("Org", "org@example.com")],
+            to: Some(vec![EmlAddr::Single(mbox("A", "a@example.org"))]),
+            cc: Some(vec![EmlAddr::Single(mbox("", "b@example.org"))]),
+            subject: Some("Planning".into()),
+            ..Default::default()
+        };
+        let options = ComposeOptions {
+            invite: Some(Invite {
+                uid: None,
+                sequence: 0,
+                start: DateTime::parse_from_rfc3339("2026-07-01T10:00:00+01:00").unwrap(),
+                end: DateTime::parse_from_rfc3339("2026-07-01T11:00:00+01:00").unwrap(),
+                all_day: false,
+                location: Some("Room 1".into()),
+            }),
+            ..Default::default()
+        };
+
+        let eml = format_message(&meta, "Agenda TBC".into(), vec![], &options).unwrap();
+        let parsed = mailparse::parse_mail(eml.as_bytes()).unwrap();
+        assert_eq!(parsed.ctype.mimetype, "multipart/alternative");
+        assert_eq!(
+            parsed.subparts[1].ctype.params.get("method").unwrap(),
+            "REQUEST"
+        );
+
+        let calendar = parse::parse_calendar(&parsed.subparts[1].get_body().unwrap()).unwrap();
+        let event = &calendar.events[0];
+        assert!(event.uid.ends_with("@example.com"));
+        assert_eq!(event.summary.as_deref(), Some("Planning"));
+        assert_eq!(event.description.as_deref(), Some("Agenda TBC"));
+        assert_eq!(event.organizer.as_ref().unwrap().address, "org@example.com");
+        assert_eq!(
+            event
+                .attendees
+                .iter()
+                .map(|a| (a.address.as_str(), a.role.as_deref().unwrap()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a@example.org", "REQ-PARTICIPANT"),
+                ("b@example.org", "OPT-PARTICIPANT"),
+            ]
+        );
+        assert_eq!(
+            event.start.as_ref().unwrap().datetime.to_rfc3339(),
+            "2026-07-01T09:00:00+00:00"
         );
     }
 }
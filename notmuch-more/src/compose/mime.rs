@@ -0,0 +1,381 @@
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use base64::write::EncoderWriter;
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64_STANDARD};
+use itertools::Itertools;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+
+enum PartBody {
+    Content(String),
+    /// Read and encoded as it's written, so it's never held in memory.
+    Base64File(PathBuf),
+    Multipart {
+        boundary: String,
+        parts: Vec<Part>,
+    },
+}
+
+/// A MIME entity: either a leaf with transfer-encoded content (or a file to be
+/// encoded) or a multipart container of other parts.
+pub(crate) struct Part {
+    headers: Vec<(&'static str, String)>,
+    body: PartBody,
+}
+
+impl Part {
+    pub fn new(ctype: &str, ctencoding: &str, content: String) -> Self {
+        Self {
+            headers: vec![
+                ("Content-Type", ctype.into()),
+                ("Content-Transfer-Encoding", ctencoding.into()),
+            ],
+            body: PartBody::Content(content),
+        }
+    }
+
+    pub fn base64_file(ctype: &str, path: &Path) -> Self {
+        Self {
+            headers: vec![
+                ("Content-Type", ctype.into()),
+                ("Content-Transfer-Encoding", "base64".into()),
+            ],
+            body: PartBody::Base64File(path.into()),
+        }
+    }
+
+    /// Wraps `parts` in a `multipart/{subtype}`, unless there's only one, in
+    /// which case the container would be meaningless and it's returned as-is.
+    pub fn multipart(subtype: &'static str, mut parts: Vec<Part>) -> Self {
+        if parts.len() == 1 {
+            return parts.remove(0);
+        }
+
+        let boundary = unique_boundary(&parts);
+        Self {
+            headers: vec![(
+                "Content-Type",
+                format!("multipart/{subtype}; boundary=\"{boundary}\""),
+            )],
+            body: PartBody::Multipart { boundary, parts },
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn contains(&self, needle: &str) -> bool {
+        self.headers.iter().any(|(_, v)| v.contains(needle))
+            || match &self.body {
+                PartBody::Content(content) => content.contains(needle),
+                // Boundaries aren't in the base64 alphabet.
+                PartBody::Base64File(_) => false,
+                PartBody::Multipart { parts, .. } => parts.iter().any(|p| p.contains(needle)),
+            }
+    }
+
+    pub fn write_to(&self, w: &mut dyn Write) -> io::Result<()> {
+        write!(w, "{}\r\n\r\n", format_headers(&self.headers))?;
+
+        match &self.body {
+            PartBody::Content(content) => w.write_all(content.as_bytes()),
+            PartBody::Base64File(path) => {
+                let mut encoder =
+                    EncoderWriter::new(LineWrapper::new(&mut *w, 76), &BASE64_STANDARD);
+                io::copy(&mut File::open(path)?, &mut encoder)?;
+                encoder.finish().map(|_| ())
+            }
+            PartBody::Multipart { boundary, parts } => {
+                for part in parts {
+                    write!(w, "--{boundary}\r\n")?;
+                    part.write_to(w)?;
+                    write!(w, "\r\n")?;
+                }
+                write!(w, "--{boundary}--\r\n")
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn render(&self) -> String {
+        let mut buf = vec![];
+        self.write_to(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+/// Breaks what's written through it into CRLF-separated lines of `width`.
+struct LineWrapper<W: Write> {
+    inner: W,
+    width: usize,
+    column: usize,
+}
+
+impl<W: Write> LineWrapper<W> {
+    fn new(inner: W, width: usize) -> Self {
+        Self {
+            inner,
+            width,
+            column: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for LineWrapper<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            // Broken lazily, so as not to end with an empty line.
+            if self.column == self.width {
+                self.inner.write_all(b"\r\n")?;
+                self.column = 0;
+            }
+
+            let (line, next) = rest.split_at(rest.len().min(self.width - self.column));
+            self.inner.write_all(line)?;
+            self.column += line.len();
+            rest = next;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn format_headers(headers: &[(&'static str, String)]) -> String {
+    headers
+        .iter()
+        .map(|(k, v)| format!("{k}: {v}"))
+        .join("\r\n")
+}
+
+const TSPECIALS: &str = "()<>@,;:\\\"/[]?=";
+
+// Short enough that a segment and its name fit on a folded line.
+const MAX_PARAM_SEGMENT: usize = 60;
+
+/// Formats a header parameter, quoting the value only if it's not a token, and
+/// using RFC 2231 extended (and if long, continued) parameters for non-ASCII.
+pub(crate) fn format_param(name: &str, value: &str) -> String {
+    let is_token = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !TSPECIALS.contains(c));
+    if is_token && value.len() <= MAX_PARAM_SEGMENT {
+        return format!("{name}={value}");
+    }
+
+    if value.is_ascii() && !value.chars().any(|c| c.is_ascii_control()) {
+        let chunks = value.chars().chunks(MAX_PARAM_SEGMENT);
+        let segments = chunks
+            .into_iter()
+            .map(|c| {
+                c.collect::<String>()
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+            })
+            .collect::<Vec<_>>();
+        return match segments.len() {
+            1 => format!("{name}=\"{}\"", segments[0]),
+            _ => segments
+                .iter()
+                .enumerate()
+                .map(|(i, s)| format!("{name}*{i}=\"{s}\""))
+                .join(";\r\n "),
+        };
+    }
+
+    let encoded = value
+        .bytes()
+        .map(
+            |b| match b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                true => (b as char).to_string(),
+                false => format!("%{b:02X}"),
+            },
+        )
+        .collect::<Vec<_>>();
+    let mut segments: Vec<String> = vec!["utf-8''".into()];
+    for e in encoded {
+        if segments.last().unwrap().len() + e.len() > MAX_PARAM_SEGMENT {
+            segments.push(String::new());
+        }
+        segments.last_mut().unwrap().push_str(&e);
+    }
+
+    match segments.len() {
+        1 => format!("{name}*={}", segments[0]),
+        _ => segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| format!("{name}*{i}*={s}"))
+            .join(";\r\n "),
+    }
+}
+
+/// Formats a header parameter for clients that predate RFC 2231, which widely
+/// (if non-standardly) understand RFC 2047 encoded-words in quoted values.
+pub(crate) fn format_legacy_param(name: &str, value: &str) -> String {
+    match value.is_ascii() {
+        true => format_param(name, value),
+        false => format!(
+            "{name}=\"=?utf-8?B?{}?=\"",
+            BASE64_STANDARD.encode(value.as_bytes())
+        ),
+    }
+}
+
+fn unique_boundary(parts: &[Part]) -> String {
+    loop {
+        // `=_` can't occur in base64 or quoted-printable output, but 8bit
+        // content could contain anything, so check regardless.
+        let boundary = format!(
+            "=_amail_{}",
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(24)
+                .map(char::from)
+                .collect::<String>()
+        );
+
+        if !parts.iter().any(|p| p.contains(&boundary)) {
+            return boundary;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boundary_of(rendered: &str) -> &str {
+        rendered
+            .split_once("boundary=\"")
+            .and_then(|(_, r)| r.split_once('"'))
+            .map(|(b, _)| b)
+            .unwrap()
+    }
+
+    #[test]
+    fn token_param_unquoted() {
+        assert_eq!(format_param("size", "1234"), "size=1234");
+    }
+
+    #[test]
+    fn special_param_quoted() {
+        assert_eq!(
+            format_param("filename", "my \"report\"; final.pdf"),
+            "filename=\"my \\\"report\\\"; final.pdf\"",
+        );
+    }
+
+    #[test]
+    fn long_param_continued() {
+        let value = format!("{}.txt", "a b".repeat(30));
+        let param = format_param("filename", &value);
+
+        assert_eq!(param.matches(";\r\n ").count(), 1);
+        assert!(param.starts_with("filename*0=\""));
+        assert!(param.contains("filename*1=\""));
+    }
+
+    #[test]
+    fn non_ascii_param_extended() {
+        assert_eq!(
+            format_param("filename", "résumé 1.pdf"),
+            "filename*=utf-8''r%C3%A9sum%C3%A9%201.pdf",
+        );
+        assert_eq!(
+            format_legacy_param("name", "résumé.pdf"),
+            "name=\"=?utf-8?B?csOpc3Vtw6kucGRm?=\"",
+        );
+    }
+
+    #[test]
+    fn params_parse_back() {
+        let name = format!("{} ✓.pdf", "Ünïcödé ".repeat(10));
+        let rendered = format!(
+            "Content-Type: application/pdf\r\nContent-Disposition: attachment;\r\n {};\r\n {}\r\n\r\n",
+            format_param("filename", &name),
+            format_param("size", "42"),
+        );
+        let parsed = mailparse::parse_mail(rendered.as_bytes()).unwrap();
+        let disposition = parsed.get_content_disposition();
+
+        assert_eq!(disposition.params.get("filename"), Some(&name));
+        assert_eq!(disposition.params.get("size"), Some(&"42".to_string()));
+    }
+
+    #[test]
+    fn single_part_is_not_wrapped() {
+        let part = Part::multipart(
+            "mixed",
+            vec![Part::new("text/plain; charset=utf-8", "8bit", "hi".into())],
+        );
+
+        assert_eq!(
+            part.render(),
+            "Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\nhi",
+        );
+    }
+
+    #[test]
+    fn multipart_boundaries_unique() {
+        let text = Part::new("text/plain", "8bit", "--=_amail_ lookalike".into());
+        let attachment = Part::new("application/octet-stream", "base64", "AAAA".into());
+        let rendered = Part::multipart("mixed", vec![text, attachment]).render();
+        let boundary = boundary_of(&rendered);
+
+        assert!(boundary.starts_with("=_amail_"));
+        assert_eq!(rendered.matches(&format!("--{boundary}\r\n")).count(), 2);
+        assert!(rendered.ends_with(&format!("\r\n--{boundary}--\r\n")));
+    }
+
+    #[test]
+    fn base64_file_streamed_in_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        let data = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+        std::fs::write(&path, &data).unwrap();
+
+        let rendered = Part::base64_file("application/octet-stream", &path).render();
+        let (_, content) = rendered.split_once("\r\n\r\n").unwrap();
+
+        assert!(content.split("\r\n").all(|l| l.len() <= 76));
+        assert!(!content.ends_with("\r\n"));
+        assert_eq!(
+            BASE64_STANDARD.decode(content.replace("\r\n", "")).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn nested_alternative_in_mixed() {
+        let alternative = Part::multipart(
+            "alternative",
+            vec![
+                Part::new("text/plain", "8bit", "hi".into()),
+                Part::new("text/html", "8bit", "<p>hi</p>".into()),
+            ],
+        );
+        let attachment = Part::new("application/octet-stream", "base64", "AAAA".into());
+        let rendered = Part::multipart("mixed", vec![alternative, attachment]).render();
+
+        let outer = boundary_of(&rendered);
+        let (_, inner_part) = rendered.split_once("multipart/alternative").unwrap();
+        let inner = boundary_of(inner_part);
+
+        assert!(rendered.starts_with("Content-Type: multipart/mixed"));
+        assert_ne!(outer, inner);
+        assert_eq!(rendered.matches(&format!("--{inner}--")).count(), 1);
+        assert_eq!(rendered.matches(&format!("--{outer}--")).count(), 1);
+    }
+}
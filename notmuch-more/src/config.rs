@@ -0,0 +1,58 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use tempfile::NamedTempFile;
+
+use crate::NotmuchMoreError;
+
+/// Reads the JSON `file` in config directory `dir`, or the default if there's
+/// none yet.
+pub(crate) fn load_json<T: Default + DeserializeOwned>(
+    dir: &Path,
+    file: &str,
+) -> Result<T, NotmuchMoreError> {
+    match fs::read(dir.join(file)) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replaces the JSON `file` in config directory `dir` with `value`, atomically.
+pub(crate) fn store_json<T: Serialize>(
+    dir: &Path,
+    file: &str,
+    value: &T,
+) -> Result<(), NotmuchMoreError> {
+    fs::create_dir_all(dir)?;
+    let mut tmp = NamedTempFile::new_in(dir)?;
+    serde_json::to_writer_pretty(&mut tmp, value)?;
+    tmp.persist(dir.join(file)).map_err(|e| e.error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn missing_file_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new");
+
+        assert!(
+            load_json::<BTreeMap<String, u32>>(&path, "a.json")
+                .unwrap()
+                .is_empty()
+        );
+        store_json(&path, "a.json", &BTreeMap::from([("x", 1)])).unwrap();
+        assert_eq!(
+            load_json::<BTreeMap<String, u32>>(&path, "a.json").unwrap(),
+            BTreeMap::from([("x".to_string(), 1)])
+        );
+    }
+}
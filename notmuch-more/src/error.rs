@@ -10,6 +10,8 @@ pub enum NotmuchMoreError {
         source: std::io::Error,
     },
     #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+    #[error(transparent)]
     LettreAddressError(#[from] lettre::address::AddressError),
     #[error(transparent)]
     LettreError(#[from] lettre::error::Error),
@@ -0,0 +1,170 @@
+use anyhow::anyhow;
+use notmuch::Database;
+use notmuch::Message;
+
+use crate::error::NotmuchMoreError;
+use crate::parse;
+use crate::parse::CalEvent;
+
+const PROCESSED_PROPERTY: &str = "amail.itip";
+const UID_PROPERTY: &str = "amail.invite-uid";
+/// Followed by the attendee's address, with their participation status as the value.
+const PARTSTAT_PROPERTY: &str = "amail.partstat.";
+/// Followed by the attendee's address, with the SEQUENCE and DTSTAMP of what
+/// their participation status was last set from as the value.
+const STAMP_PROPERTY: &str = "amail.partstat-stamp.";
+
+/// Orders the versions of an event (RFC 5546 §2.1.5): by SEQUENCE, then
+/// DTSTAMP, with none older than any.
+fn stamp(event: &CalEvent) -> (u32, Option<i64>) {
+    (event.sequence, event.dtstamp.map(|d| d.timestamp()))
+}
+
+fn parse_stamp(value: &str) -> Option<(u32, Option<i64>)> {
+    let (sequence, dtstamp) = value.split_once(' ').unwrap_or((value, ""));
+    Some((sequence.parse().ok()?, dtstamp.parse().ok()))
+}
+
+/// Sets `address`'s participation status from `event`, unless it was already
+/// set from a newer version of it. Returns whether it was.
+fn set_partstat(
+    invite: &Message,
+    address: &str,
+    partstat: &str,
+    event: &CalEvent,
+) -> Result<bool, NotmuchMoreError> {
+    let address = address.to_lowercase();
+    let stamp_key = format!("{STAMP_PROPERTY}{address}");
+    let stamp = stamp(event);
+    let stored = invite
+        .properties(&stamp_key, true)
+        .find_map(|(_, v)| parse_stamp(&v));
+    if stored.is_some_and(|s| s > stamp) {
+        return Ok(false);
+    }
+
+    let key = format!("{PARTSTAT_PROPERTY}{address}");
+    invite.remove_all_properties(Some(&key))?;
+    invite.add_property(&key, partstat)?;
+    invite.remove_all_properties(Some(&stamp_key))?;
+    invite.add_property(
+        &stamp_key,
+        &match stamp {
+            (sequence, Some(dtstamp)) => format!("{sequence} {dtstamp}"),
+            (sequence, None) => sequence.to_string(),
+        },
+    )?;
+    Ok(true)
+}
+
+/// Records the UIDs and attendees of sent invitations, and updates them with
+/// the attendance given in any replies since: each only by the attendee who
+/// sent it, and only if not older than what's recorded. Returns the number of
+/// replies that updated an invitation.
+pub fn process_calendars(db: &Database) -> Result<usize, NotmuchMoreError> {
+    println!("Processing calendar messages");
+    let query = db.create_query(&format!(
+        "mimetype:text/calendar and not property:{PROCESSED_PROPERTY}=processed"
+    ))?;
+    // Oldest first, so that invitations are recorded before their replies.
+    query.set_sort(notmuch::Sort::OldestFirst);
+
+    let mut replies = 0;
+    for msg in query.search_messages()? {
+        let (senders, calendar) = match parse::parse_eml(db, msg.id().into()) {
+            Ok((meta, body)) => (meta.from, parse::calendar(&body).cloned()),
+            Err(e) => {
+                println!("[WARN] Failed to parse {}: {e:?}", msg.id());
+                (vec![], None)
+            }
+        };
+
+        match calendar {
+            Some(c)
+                if c.method.as_deref() == Some("REQUEST") && msg.tags().any(|t| t == "sent") =>
+            {
+                for event in &c.events {
+                    println!("[TRACE] Recording invitation {}", event.uid);
+                    msg.add_property(UID_PROPERTY, &event.uid)?;
+                    for attendee in &event.attendees {
+                        let partstat = attendee.partstat.as_deref().unwrap_or("NEEDS-ACTION");
+                        set_partstat(&msg, &attendee.address, partstat, event)?;
+                    }
+                }
+            }
+            Some(c) if c.method.as_deref() == Some("REPLY") => {
+                let mut updated = false;
+                for event in &c.events {
+                    let invites = db.create_query(&format!(
+                        "tag:sent and property:{UID_PROPERTY}=\"{}\"",
+                        event.uid.replace('"', "\"\"")
+                    ))?;
+                    for invite in invites.search_messages()? {
+                        println!("[TRACE] Updating attendance of {}", invite.id());
+                        for attendee in &event.attendees {
+                            let Some(partstat) = &attendee.partstat else {
+                                continue;
+                            };
+                            if !senders
+                                .iter()
+                                .any(|s| s.address.eq_ignore_ascii_case(&attendee.address))
+                            {
+                                println!(
+                                    "[WARN] Ignoring reply for {} not sent by them",
+                                    attendee.address
+                                );
+                            } else if set_partstat(&invite, &attendee.address, partstat, event)? {
+                                updated = true;
+                            } else {
+                                println!(
+                                    "[WARN] Ignoring outdated reply from {}",
+                                    attendee.address
+                                );
+                            }
+                        }
+                    }
+                }
+                replies += usize::from(updated);
+            }
+            _ => (),
+        }
+
+        msg.add_property(PROCESSED_PROPERTY, "processed")?;
+    }
+
+    Ok(replies)
+}
+
+/// The attendees of sent invitation `id`, with their participation status.
+pub fn attendance(db: &Database, id: String) -> Result<Vec<(String, String)>, NotmuchMoreError> {
+    println!("Getting attendance of {id}");
+    let msg = db
+        .find_message(&id)?
+        .ok_or_else(|| anyhow!("Message {} not found", id))?;
+    Ok(msg
+        .properties(PARTSTAT_PROPERTY, false)
+        .map(|(k, v)| (k.trim_start_matches(PARTSTAT_PROPERTY).to_string(), v))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_ordered() {
+        let event = |sequence, dtstamp: &str| CalEvent {
+            sequence,
+            dtstamp: chrono::DateTime::parse_from_rfc3339(dtstamp).ok(),
+            ..Default::default()
+        };
+        let stored = parse_stamp("1 1780304400").unwrap();
+
+        assert_eq!(stored, stamp(&event(1, "2026-06-01T09:00:00Z")));
+        assert!(stamp(&event(1, "2026-06-01T08:59:59Z")) < stored);
+        assert!(stamp(&event(0, "2026-07-01T00:00:00Z")) < stored);
+        assert!(stamp(&event(2, "")) > stored);
+        assert_eq!(parse_stamp("3"), Some((3, None)));
+        assert!(parse_stamp("3").unwrap() < (3, Some(0)));
+    }
+}
@@ -1,11 +1,21 @@
+pub mod attachment_text;
+pub mod attachments;
 pub mod compose;
 pub mod database;
 pub mod error;
+pub mod invites;
 pub mod parse;
 pub mod query;
+pub mod remote_content;
 pub mod smtp;
 pub mod tags;
+pub mod templates;
+pub mod unsubscribe;
+
+mod config;
 
 pub use database::Database;
 pub use error::NotmuchMoreError;
+pub use remote_content::RemoteContentPolicies;
 pub use smtp::Smtp;
+pub use templates::Templates;
@@ -6,17 +6,60 @@ use notmuch::Database;
 use crate::NotmuchMoreError;
 
 mod addresses;
+mod auth_results;
 mod body;
+mod calendar;
+mod charset;
+mod dkim;
 mod error;
+mod flowed;
 mod headers;
-
+mod html;
+mod links;
+mod list;
+mod received;
+mod sanitise;
+mod timezones;
+mod tnef;
+
+pub(crate) use body::find_part;
+pub(crate) use body::leaf_parts;
+pub(crate) use body::tnef_part;
+pub(crate) use charset::decode;
+pub(crate) use dkim::SigningKey;
+pub(crate) use dkim::dkim_signature;
 pub(crate) use headers::Rfc5322Fields;
+pub(crate) use tnef::is_tnef;
+pub(crate) use tnef::parse_tnef;
+#[cfg(test)]
+pub(crate) use tnef::tests::sample as tnef_sample;
 
 pub use addresses::EmlAddr;
 pub use addresses::Mailbox;
+pub use auth_results::AuthResult;
+pub use auth_results::AuthResults;
+pub use auth_results::AuthVerdict;
 pub use body::EmlBody;
+pub use calendar::Attendee;
+pub use calendar::CalEvent;
+pub use calendar::CalTime;
+pub use calendar::Calendar;
+pub use calendar::PartStat;
+pub use calendar::parse_calendar;
+pub use dkim::DkimVerdict;
+pub use dkim::DnsTxtResolver;
+pub use dkim::FileTxtResolver;
+pub use dkim::TxtResolver;
+pub use dkim::verify_dkim;
 pub use error::EmlParseError;
 pub use headers::EmlMeta;
+pub use headers::generate_message_id;
+pub use html::html_to_text;
+pub use links::LinkRisk;
+pub use links::LinkWarning;
+pub use list::MailingList;
+pub use received::Hop;
+pub use sanitise::RemoteContent;
 
 pub fn parse_address(addr: &str) -> Result<Vec<Mailbox>, NotmuchMoreError> {
     let mboxes = mailparse::addrparse(addr)
@@ -29,6 +72,15 @@ pub fn parse_address(addr: &str) -> Result<Vec<Mailbox>, NotmuchMoreError> {
 }
 
 pub fn parse_eml(db: &Database, id: String) -> Result<(EmlMeta, EmlBody), NotmuchMoreError> {
+    parse_eml_with_remote(db, id, RemoteContent::Block)
+}
+
+/// Parses the message, loading remote content in its HTML only if `remote` allows.
+pub fn parse_eml_with_remote(
+    db: &Database,
+    id: String,
+    remote: RemoteContent,
+) -> Result<(EmlMeta, EmlBody), NotmuchMoreError> {
     println!("Opening id:{id}");
     let msg = db
         .find_message(&id)?
@@ -38,10 +90,27 @@ pub fn parse_eml(db: &Database, id: String) -> Result<(EmlMeta, EmlBody), Notmuc
     println!("Parsing id:{id}");
     let meta =
         EmlMeta::try_from(&msg).map_err(|e| anyhow!("Could not parse {}: {}", id, e.reason))?;
-    let body = body::parse_body_part(&mailparse::parse_mail(contents)?)?;
+    let body = body::parse_body(&mailparse::parse_mail(contents)?, remote)?;
     Ok((meta, body))
 }
 
+pub fn parse_meta(db: &Database, id: String) -> Result<EmlMeta, NotmuchMoreError> {
+    let msg = db
+        .find_message(&id)?
+        .ok_or_else(|| anyhow!("Message {} not found", id))?;
+    Ok(EmlMeta::try_from(&msg).map_err(|e| anyhow!("Could not parse {}: {}", id, e.reason))?)
+}
+
+/// The servers message `id` passed through on its way here, in order.
+pub fn delivery_path(db: &Database, id: String) -> Result<Vec<Hop>, NotmuchMoreError> {
+    let msg = db
+        .find_message(&id)?
+        .ok_or_else(|| anyhow!("Message {} not found", id))?;
+    Ok(received::parse_received(&headers::read_raw_headers(
+        msg.filename(),
+    )?))
+}
+
 pub fn plaintext(eml: &EmlBody) -> Option<String> {
     let mut candidates: Vec<&EmlBody> = eml.alternatives.iter().collect();
     candidates.push(eml);
@@ -51,6 +120,25 @@ pub fn plaintext(eml: &EmlBody) -> Option<String> {
         .map(|e| e.content.clone())
 }
 
+/// The plaintext of the message, or if there's none, its HTML converted to text.
+pub fn text(eml: &EmlBody) -> Option<String> {
+    plaintext(eml).or_else(|| {
+        let mut candidates: Vec<&EmlBody> = eml.alternatives.iter().collect();
+        candidates.push(eml);
+        candidates
+            .iter()
+            .find(|&e| e.is_cleaned_html)
+            .map(|e| html_to_text(&e.content))
+    })
+}
+
+/// The first calendar in the message, whether an alternative or attached.
+pub fn calendar(eml: &EmlBody) -> Option<&Calendar> {
+    eml.calendar
+        .as_ref()
+        .or_else(|| eml.alternatives.iter().chain(&eml.extra).find_map(calendar))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,6 +184,25 @@ mod tests {
         assert_eq!(plaintext(&eml), Some("plaintext!".into()));
     }
 
+    #[test]
+    fn text_falls_back_to_html() {
+        let eml = EmlBody {
+            alternatives: vec![EmlBody {
+                content: "<p>Hello <a href=\"https://example.com/x\">there</a></p>".into(),
+                is_cleaned_html: true,
+                mimetype: "text/html".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(plaintext(&eml), None);
+        assert_eq!(
+            text(&eml),
+            Some("Hello there[1]\r\n\r\n[1] https://example.com/x".into())
+        );
+    }
+
     #[test]
     fn simple_addr() {
         let addr = "Foo Bar <foo@bar.com>";
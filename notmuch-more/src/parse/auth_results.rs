@@ -0,0 +1,293 @@
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthResult {
+    Pass,
+    Fail,
+    SoftFail,
+    Neutral,
+    None,
+    Policy,
+    TempError,
+    PermError,
+    Other,
+}
+
+impl From<&str> for AuthResult {
+    fn from(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "pass" => Self::Pass,
+            "fail" | "hardfail" => Self::Fail,
+            "softfail" => Self::SoftFail,
+            "neutral" => Self::Neutral,
+            "none" => Self::None,
+            "policy" => Self::Policy,
+            "temperror" => Self::TempError,
+            "permerror" => Self::PermError,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AuthVerdict {
+    pub result: AuthResult,
+    /// What passed or failed: the envelope sender's (or HELO) domain for SPF,
+    /// the signing domain for DKIM, the From domain for DMARC.
+    pub domain: Option<String>,
+}
+
+/// The results one server recorded in an `Authentication-Results` header.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AuthResults {
+    pub authserv_id: String,
+    pub spf: Option<AuthVerdict>,
+    pub dkim: Vec<AuthVerdict>,
+    pub dmarc: Option<AuthVerdict>,
+}
+
+/// Splits on `sep` outside of quoted strings, having dropped (nested)
+/// comments.
+fn split_unquoted(value: &str, sep: impl Fn(char) -> bool) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quoted = false;
+    let mut depth = 0;
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    parts.last_mut().unwrap().push(escaped);
+                }
+            }
+            '"' if depth == 0 => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted && depth > 0 => depth -= 1,
+            _ if depth > 0 => (),
+            c if !quoted && sep(c) => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+        .into_iter()
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+fn domain_of(value: &str) -> String {
+    value
+        .rsplit_once('@')
+        .map_or(value, |(_, d)| d)
+        .trim_end_matches('.')
+        .to_lowercase()
+}
+
+fn parse_header(value: &str) -> Option<AuthResults> {
+    let value = Regex::new(r"\s*=\s*").unwrap().replace_all(value, "=");
+    let mut parts = split_unquoted(&value, |c| c == ';').into_iter();
+
+    let authserv_id = parts.next()?.split_whitespace().next()?.to_lowercase();
+    let mut results = AuthResults {
+        authserv_id,
+        ..Default::default()
+    };
+
+    for resinfo in parts {
+        let tokens = split_unquoted(&resinfo, char::is_whitespace);
+        let Some((method, result)) = tokens.first().and_then(|t| t.split_once('=')) else {
+            continue;
+        };
+        let property = |names: &[&str]| {
+            names.iter().find_map(|name| {
+                tokens[1..]
+                    .iter()
+                    .find_map(|t| {
+                        t.split_once('=')
+                            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+                    })
+                    .map(|(_, v)| domain_of(v))
+            })
+        };
+        let method = method.split('/').next().unwrap_or_default().to_lowercase();
+        let result = AuthResult::from(result);
+
+        match method.as_str() {
+            "spf" => {
+                results.spf = Some(AuthVerdict {
+                    result,
+                    domain: property(&["smtp.mailfrom", "smtp.helo"]),
+                })
+            }
+            "dkim" => results.dkim.push(AuthVerdict {
+                result,
+                domain: property(&["header.d", "header.i"]),
+            }),
+            "dmarc" => {
+                results.dmarc = Some(AuthVerdict {
+                    result,
+                    domain: property(&["header.from"]),
+                })
+            }
+            _ => (),
+        }
+    }
+    Some(results)
+}
+
+/// Parses `(name, value)` headers' authentication results, keeping only those
+/// from `trusted` authserv-ids (our own receiving servers, which remove any
+/// forged ones): anyone can add a header claiming a pass. ARC ones aren't
+/// believed either, being left to `parse_arc_results`.
+pub(crate) fn parse_auth_results(
+    headers: &[(String, String)],
+    trusted: &[String],
+) -> Vec<AuthResults> {
+    headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Authentication-Results"))
+        .filter_map(|(_, value)| parse_header(value))
+        .filter(|r| {
+            trusted
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(&r.authserv_id))
+        })
+        .collect()
+}
+
+/// Parses `(name, value)` headers' ARC authentication results, as recorded
+/// by each server a message was forwarded through, most recent first. These
+/// are unverified: their seals aren't checked, so anyone could have added one.
+pub(crate) fn parse_arc_results(headers: &[(String, String)]) -> Vec<AuthResults> {
+    headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("ARC-Authentication-Results"))
+        .filter_map(|(_, value)| {
+            // Without the instance tag (`i=1;`) that precedes the authserv-id.
+            let (instance, rest) = value.split_once(';')?;
+            let is_instance = instance
+                .trim()
+                .split_once('=')
+                .is_some_and(|(k, _)| k.trim().eq_ignore_ascii_case("i"));
+            parse_header(if is_instance { rest } else { value })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(h: &[(&str, &str)]) -> Vec<(String, String)> {
+        h.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn verdicts_parsed() {
+        let results = parse_auth_results(
+            &headers(&[(
+                "Authentication-Results",
+                "mx.example.net 1;\r\n dkim=pass (2048-bit key; unprotected) header.d=sender.com header.s=s1 header.b=\"ab;cd\";\r\n dkim=fail header.i=@list.org;\r\n spf = softfail (domain of bounce@Sender.COM does not designate ...) smtp.mailfrom=bounce@Sender.COM;\r\n dmarc=pass (p=REJECT) header.from=sender.com",
+            )]),
+            &["MX.example.net".into()],
+        );
+
+        assert_eq!(
+            results,
+            vec![AuthResults {
+                authserv_id: "mx.example.net".into(),
+                spf: Some(AuthVerdict {
+                    result: AuthResult::SoftFail,
+                    domain: Some("sender.com".into()),
+                }),
+                dkim: vec![
+                    AuthVerdict {
+                        result: AuthResult::Pass,
+                        domain: Some("sender.com".into()),
+                    },
+                    AuthVerdict {
+                        result: AuthResult::Fail,
+                        domain: Some("list.org".into()),
+                    },
+                ],
+                dmarc: Some(AuthVerdict {
+                    result: AuthResult::Pass,
+                    domain: Some("sender.com".into()),
+                }),
+            }]
+        );
+    }
+
+    #[test]
+    fn only_trusted_kept() {
+        let results = parse_auth_results(
+            &headers(&[
+                ("Authentication-Results", "mx.example.net; none"),
+                (
+                    "Authentication-Results",
+                    "forger.example; dmarc=pass header.from=bank.com",
+                ),
+                (
+                    "ARC-Authentication-Results",
+                    "i=1; mx.example.net; dmarc=pass header.from=bank.com",
+                ),
+                (
+                    "Authentication-Results",
+                    "mx.google.com; spf=pass smtp.helo=relay.org",
+                ),
+                ("Subject", "mx.example.net; dmarc=pass"),
+            ]),
+            &["mx.example.net".into(), "mx.google.com".into()],
+        );
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].spf, None);
+        assert_eq!(results[0].dmarc, None);
+        assert_eq!(results[1].authserv_id, "mx.google.com");
+        assert_eq!(
+            results[1].spf.as_ref().unwrap().domain.as_deref(),
+            Some("relay.org")
+        );
+
+        assert!(
+            parse_auth_results(
+                &headers(&[("Authentication-Results", "mx.example.net; spf=pass")]),
+                &[]
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn arc_parsed_separately() {
+        let results = parse_arc_results(&headers(&[
+            (
+                "ARC-Authentication-Results",
+                "i=2; lists.example.org; dkim=pass header.d=sender.com",
+            ),
+            ("Authentication-Results", "mx.example.net; spf=pass"),
+            (
+                "ARC-Authentication-Results",
+                "i = 1 ; mx.sender.com; spf=fail smtp.mailfrom=sender.com",
+            ),
+        ]));
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].authserv_id, "lists.example.org");
+        assert_eq!(results[0].dkim[0].result, AuthResult::Pass);
+        assert_eq!(results[1].authserv_id, "mx.sender.com");
+        assert_eq!(
+            results[1].spf,
+            Some(AuthVerdict {
+                result: AuthResult::Fail,
+                domain: Some("sender.com".into()),
+            })
+        );
+    }
+}
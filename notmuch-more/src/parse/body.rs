@@ -1,28 +1,189 @@
+use std::collections::HashMap;
+
 use anyhow::anyhow;
 use email::MimeMultipartType;
 use email::mimeheaders::MimeContentType;
 use itertools::Itertools;
-use regex::Regex;
+use mailparse::MailHeaderMap;
 use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
 
+use super::Calendar;
+use super::calendar;
+use super::charset;
+use super::flowed;
+use super::links;
+use super::links::LinkWarning;
+use super::sanitise;
+use super::sanitise::RemoteContent;
+use super::tnef;
 use crate::NotmuchMoreError;
 
 #[derive(Clone, Debug, Default, Serialize)]
 pub struct EmlBody {
     pub alternatives: Vec<EmlBody>,
+    /// For `text/calendar` parts, the parsed calendar.
+    pub calendar: Option<Calendar>,
+    /// For text parts, the charset they were actually decoded as, which may
+    /// not be the one declared.
+    pub charset: Option<String>,
     pub content: String,
-    pub content_base64: Option<String>,
-    pub content_encoded: Option<Vec<u8>>,
+    /// Size of the part's content once transfer-decoded.
+    pub decoded_size: usize,
     pub disposition: String,
     pub extra: Vec<EmlBody>,
     pub filename: Option<String>,
+    /// Whether `content` is HTML sanitised for display, keeping only inline
+    /// styles (not `<style>` sheets), and with `cid:` images' sources replaced
+    /// by the `data-part-id` of the part to fetch.
     pub is_cleaned_html: bool,
+    /// For HTML, its links that may be phishing.
+    pub link_warnings: Vec<LinkWarning>,
     pub mimetype: String,
+    /// The IMAP-style MIME part path, e.g. `1.2`, by which it can be fetched.
+    pub part_id: String,
+    /// Number of remote images in HTML not loaded, as not allowed.
+    pub remote_content_blocked: usize,
+    /// SHA-256 of the decoded content, as hex.
+    pub sha256: String,
     pub signature: Option<Box<EmlBody>>,
+    /// As given in the Content-Disposition, if at all.
     pub size: Option<String>,
+    /// Number of tracking images removed from HTML, whether remote content
+    /// was allowed or not.
+    pub trackers_removed: usize,
+}
+
+fn child_id(parent: &str, i: usize) -> String {
+    match parent.is_empty() {
+        true => format!("{}", i + 1),
+        false => format!("{parent}.{}", i + 1),
+    }
+}
+
+/// Finds the part at IMAP-style path `part_id`, where a message that isn't
+/// multipart has just the one part, `1`.
+pub(crate) fn find_part<'a, 'b>(
+    mail: &'b mailparse::ParsedMail<'a>,
+    part_id: &str,
+) -> Option<&'b mailparse::ParsedMail<'a>> {
+    if mail.subparts.is_empty() {
+        return (part_id == "1").then_some(mail);
+    }
+
+    let mut part = mail;
+    for n in part_id.split('.') {
+        part = part
+            .subparts
+            .get(n.parse::<usize>().ok()?.checked_sub(1)?)?;
+    }
+    Some(part)
+}
+
+/// All the non-multipart parts, with their IMAP-style paths.
+pub(crate) fn leaf_parts<'a, 'b>(
+    mail: &'b mailparse::ParsedMail<'a>,
+    part_id: &str,
+) -> Vec<(String, &'b mailparse::ParsedMail<'a>)> {
+    match mail.subparts.is_empty() {
+        true => vec![(leaf_id(part_id), mail)],
+        false => mail
+            .subparts
+            .iter()
+            .enumerate()
+            .flat_map(|(i, p)| leaf_parts(p, &child_id(part_id, i)))
+            .collect(),
+    }
+}
+
+/// Part `part_id` decoded from a TNEF part, by which it's the parent.
+pub(crate) fn tnef_part(mail: &mailparse::ParsedMail, part_id: &str) -> Option<tnef::TnefPart> {
+    let (parent, n) = part_id.rsplit_once('.')?;
+    let parent = find_part(mail, parent).filter(|p| tnef::is_tnef(p))?;
+    tnef::parse_tnef(&parent.get_body_raw().ok()?)
+        .ok()?
+        .into_iter()
+        .nth(n.parse::<usize>().ok()?.checked_sub(1)?)
+}
+
+fn leaf_id(part_id: &str) -> String {
+    match part_id.is_empty() {
+        true => "1".into(),
+        false => part_id.into(),
+    }
+}
+
+fn html_body(
+    html: &str,
+    remote: RemoteContent,
+    cids: &HashMap<String, String>,
+    leaf: EmlBody,
+) -> EmlBody {
+    let clean = sanitise::clean_html(html, remote, cids);
+    EmlBody {
+        link_warnings: links::link_warnings(&clean.html),
+        content: clean.html,
+        is_cleaned_html: true,
+        remote_content_blocked: clean.remote_blocked,
+        trackers_removed: clean.trackers_removed,
+        ..leaf
+    }
+}
+
+fn tnef_body(
+    part: tnef::TnefPart,
+    part_id: &str,
+    remote: RemoteContent,
+    cids: &HashMap<String, String>,
+) -> EmlBody {
+    let text = String::from_utf8_lossy(&part.content);
+    let leaf = EmlBody {
+        content: match part.mimetype.as_str() {
+            m if m.starts_with("text/") => text.to_string(),
+            _ => String::new(),
+        },
+        decoded_size: part.content.len(),
+        disposition: format!("{:?}", part.disposition),
+        filename: part.filename,
+        mimetype: part.mimetype,
+        part_id: part_id.into(),
+        sha256: format!("{:x}", Sha256::digest(&part.content)),
+        ..Default::default()
+    };
+    match leaf.mimetype.as_str() {
+        "text/html" => html_body(&text, remote, cids, leaf),
+        _ => leaf,
+    }
+}
+
+/// The ids of the parts with a Content-ID, by it (without its `<>`).
+fn content_ids(mail: &mailparse::ParsedMail) -> HashMap<String, String> {
+    leaf_parts(mail, "")
+        .into_iter()
+        .filter_map(|(part_id, part)| {
+            let cid = part.headers.get_first_value("Content-ID")?;
+            let cid = cid.trim().trim_start_matches('<').trim_end_matches('>');
+            Some((cid.to_string(), part_id))
+        })
+        .collect()
 }
 
-pub(crate) fn parse_body_part(part: &mailparse::ParsedMail) -> Result<EmlBody, NotmuchMoreError> {
+/// Parses the body of `mail`, with HTML's `cid:` images referring to the
+/// parts they're of.
+pub(crate) fn parse_body(
+    mail: &mailparse::ParsedMail,
+    remote: RemoteContent,
+) -> Result<EmlBody, NotmuchMoreError> {
+    parse_body_part(mail, "", remote, &content_ids(mail))
+}
+
+fn parse_body_part(
+    part: &mailparse::ParsedMail,
+    part_id: &str,
+    remote: RemoteContent,
+    cids: &HashMap<String, String>,
+) -> Result<EmlBody, NotmuchMoreError> {
     let mimect: MimeContentType = part
         .ctype
         .mimetype
@@ -34,79 +195,132 @@ pub(crate) fn parse_body_part(part: &mailparse::ParsedMail) -> Result<EmlBody, N
     let err_multipart_no_subpart = anyhow!("Expected multipart body to have at least one subpart");
 
     match MimeMultipartType::from_content_type(mimect) {
-        None => match part.ctype.mimetype.as_str() {
-            "text/html" => Ok(EmlBody {
-                content: {
-                    let b = ammonia::Builder::default()
-                        .set_tag_attribute_value("a", "target", "_blank")
-                        .rm_tag_attributes("img", &["src"])
-                        .clean(&part.get_body()?);
-
-                    Regex::new("href=\"([^\"]+)")
-                        .unwrap()
-                        .replace_all(&b.to_string(), "href=\"$1\" title=\"$1\"")
-                        .into()
-                },
-                content_base64: match part.get_body_encoded() {
-                    mailparse::body::Body::Base64(body) => {
-                        String::from_utf8(body.get_raw().into()).ok()
-                    }
-                    _ => None,
-                },
-                content_encoded: Some(part.get_body_raw()?),
-                disposition: format!("{:?}", content_disp.disposition),
-                filename: content_disp.params.get("filename").map(|f| f.into()),
-                is_cleaned_html: true,
-                mimetype: part.ctype.mimetype.to_owned(),
-                size: content_disp.params.get("size").map(|f| f.into()),
-                ..Default::default()
-            }),
-            _ => Ok(EmlBody {
-                content: part.get_body()?,
-                content_base64: match part.get_body_encoded() {
-                    mailparse::body::Body::Base64(body) => {
-                        String::from_utf8(body.get_raw().into()).ok()
-                    }
-                    _ => None,
-                },
-                content_encoded: Some(part.get_body_raw()?),
+        None => {
+            let raw = part.get_body_raw()?;
+            let (text, charset) = match part.ctype.mimetype.starts_with("text/") {
+                true => {
+                    let (text, charset) =
+                        charset::decode(&raw, part.ctype.params.get("charset").map(String::as_str));
+                    (text, Some(charset.to_string()))
+                }
+                false => (String::new(), None),
+            };
+            let leaf = EmlBody {
+                charset,
+                decoded_size: raw.len(),
                 disposition: format!("{:?}", content_disp.disposition),
                 filename: content_disp.params.get("filename").map(|f| f.into()),
                 mimetype: part.ctype.mimetype.to_owned(),
+                part_id: leaf_id(part_id),
+                sha256: format!("{:x}", Sha256::digest(&raw)),
                 size: content_disp.params.get("size").map(|f| f.into()),
                 ..Default::default()
-            }),
-        },
+            };
+
+            match part.ctype.mimetype.as_str() {
+                "text/html" => Ok(html_body(&text, remote, cids, leaf)),
+                "text/plain"
+                    if part
+                        .ctype
+                        .params
+                        .get("format")
+                        .is_some_and(|f| f.eq_ignore_ascii_case("flowed")) =>
+                {
+                    Ok(EmlBody {
+                        content: flowed::unflow(
+                            &text,
+                            part.ctype
+                                .params
+                                .get("delsp")
+                                .is_some_and(|d| d.eq_ignore_ascii_case("yes")),
+                        ),
+                        ..leaf
+                    })
+                }
+                "text/calendar" => Ok(EmlBody {
+                    calendar: match calendar::parse_calendar(&text) {
+                        Ok(c) => Some(c),
+                        Err(e) => {
+                            println!("[WARN] Failed to parse calendar: {e}");
+                            None
+                        }
+                    },
+                    content: text,
+                    ..leaf
+                }),
+                _ if tnef::is_tnef(part) => Ok(EmlBody {
+                    extra: match tnef::parse_tnef(&raw) {
+                        Ok(parts) => parts
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, p)| tnef_body(p, &child_id(&leaf.part_id, i), remote, cids))
+                            .collect(),
+                        Err(e) => {
+                            println!("[WARN] Failed to decode TNEF: {e}");
+                            vec![]
+                        }
+                    },
+                    ..leaf
+                }),
+                // Anything else is fetched by part id, only if it's needed.
+                m if m.starts_with("text/") => Ok(EmlBody {
+                    content: text,
+                    ..leaf
+                }),
+                m if m.starts_with("message/") => Ok(EmlBody {
+                    content: part.get_body()?,
+                    ..leaf
+                }),
+                _ => Ok(leaf),
+            }
+        }
 
         Some(MimeMultipartType::Alternative) => {
-            let mut first =
-                parse_body_part(part.subparts.first().ok_or(err_multipart_no_subpart)?)?;
+            let mut first = parse_body_part(
+                part.subparts.first().ok_or(err_multipart_no_subpart)?,
+                &child_id(part_id, 0),
+                remote,
+                cids,
+            )?;
             first.alternatives = part.subparts[1..]
                 .iter()
-                .map(parse_body_part)
+                .enumerate()
+                .map(|(i, p)| parse_body_part(p, &child_id(part_id, i + 1), remote, cids))
                 .collect::<Result<_, _>>()?;
             Ok(first)
         }
 
         Some(MimeMultipartType::Mixed) => {
-            let mut first =
-                parse_body_part(part.subparts.first().ok_or(err_multipart_no_subpart)?)?;
+            let mut first = parse_body_part(
+                part.subparts.first().ok_or(err_multipart_no_subpart)?,
+                &child_id(part_id, 0),
+                remote,
+                cids,
+            )?;
             first.extra = part.subparts[1..]
                 .iter()
-                .map(parse_body_part)
+                .enumerate()
+                .map(|(i, p)| parse_body_part(p, &child_id(part_id, i + 1), remote, cids))
                 .collect::<Result<_, _>>()?;
 
             Ok(first)
         }
 
         Some(MimeMultipartType::Signed) => {
-            let mut first =
-                parse_body_part(part.subparts.first().ok_or(err_multipart_no_subpart)?)?;
+            let mut first = parse_body_part(
+                part.subparts.first().ok_or(err_multipart_no_subpart)?,
+                &child_id(part_id, 0),
+                remote,
+                cids,
+            )?;
             first.signature = Some(Box::new(parse_body_part(
                 part.subparts[1..]
                     .iter()
                     .exactly_one()
                     .map_err(|_| anyhow!("Expected exactly one signature for signed part"))?,
+                &child_id(part_id, 1),
+                remote,
+                cids,
             )?));
 
             Ok(first)
@@ -115,3 +329,102 @@ pub(crate) fn parse_body_part(part: &mailparse::ParsedMail) -> Result<EmlBody, N
         Some(t) => Err(anyhow!("Not implemented: {:?}", t).into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EML: &[u8] = b"Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+        --outer\r\n\
+        Content-Type: multipart/alternative; boundary=inner\r\n\r\n\
+        --inner\r\nContent-Type: text/plain\r\n\r\nHi\r\n\
+        --inner\r\nContent-Type: text/html\r\n\r\n<p>Hi</p>\r\n\
+        --inner--\r\n\
+        --outer\r\n\
+        Content-Type: application/octet-stream\r\n\
+        Content-Disposition: attachment; filename=a.bin\r\n\
+        Content-Transfer-Encoding: base64\r\n\r\nAAEC\r\n\
+        --outer--\r\n";
+
+    #[test]
+    fn part_ids() {
+        let mail = mailparse::parse_mail(EML).unwrap();
+        let body = parse_body(&mail, RemoteContent::Block).unwrap();
+
+        assert_eq!(body.part_id, "1.1");
+        assert_eq!(body.alternatives[0].part_id, "1.2");
+        assert_eq!(body.extra[0].part_id, "2");
+        assert_eq!(body.charset.as_deref(), Some("US-ASCII"));
+        assert_eq!(body.extra[0].charset, None);
+        assert_eq!(body.extra[0].content, "");
+        assert_eq!(body.extra[0].decoded_size, 3);
+        assert_eq!(
+            body.extra[0].sha256,
+            "ae4b3280e56e2faf83f414a6e3dabe9d5fbe18976544c05fed121accb85b53fc"
+        );
+        assert_eq!(
+            leaf_parts(&mail, "")
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1.1", "1.2", "2"]
+        );
+        assert_eq!(
+            find_part(&mail, "2").unwrap().get_body_raw().unwrap(),
+            vec![0, 1, 2]
+        );
+        assert!(find_part(&mail, "3").is_none());
+        assert!(find_part(&mail, "1.0").is_none());
+    }
+
+    #[test]
+    fn single_part_id() {
+        let mail = mailparse::parse_mail(b"Content-Type: text/plain\r\n\r\nHi").unwrap();
+
+        assert_eq!(
+            parse_body(&mail, RemoteContent::Block).unwrap().part_id,
+            "1"
+        );
+        assert!(find_part(&mail, "1").is_some());
+    }
+
+    #[test]
+    fn tnef_decoded_as_extra_parts() {
+        use base64::Engine;
+        let eml = format!(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nHi\r\n\
+            --b\r\nContent-Type: application/ms-tnef; name=winmail.dat\r\n\
+            Content-Transfer-Encoding: base64\r\n\r\n{}\r\n--b--\r\n",
+            base64::engine::general_purpose::STANDARD.encode(tnef::tests::sample())
+        );
+        let mail = mailparse::parse_mail(eml.as_bytes()).unwrap();
+        let tnef = &parse_body(&mail, RemoteContent::Block).unwrap().extra[0];
+
+        assert_eq!(tnef.extra[0].part_id, "2.1");
+        assert_eq!(tnef.extra[0].content, "See attached.");
+        assert_eq!(tnef.extra[1].part_id, "2.2");
+        assert_eq!(tnef.extra[1].disposition, "Attachment");
+        assert_eq!(
+            tnef_part(&mail, "2.2").unwrap().filename.as_deref(),
+            Some("Quarterly report.txt")
+        );
+        assert!(tnef_part(&mail, "1.1").is_none());
+    }
+
+    #[test]
+    fn cid_images_refer_to_parts() {
+        let eml = b"Content-Type: multipart/related; boundary=b\r\n\r\n\
+            --b\r\nContent-Type: text/html\r\n\r\n\
+            <img src=\"cid:logo%40example.com\"><img src=\"cid:missing\">\r\n\
+            --b\r\nContent-Type: image/png\r\nContent-ID: <logo@example.com>\r\n\
+            Content-Transfer-Encoding: base64\r\n\r\nAAEC\r\n--b--\r\n";
+        let mail = mailparse::parse_mail(eml).unwrap();
+        let body = parse_body(&mail, RemoteContent::Block).unwrap();
+
+        assert_eq!(
+            body.content,
+            r#"<img data-part-id="2"><img src="cid:missing">"#
+        );
+    }
+}
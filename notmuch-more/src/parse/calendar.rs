@@ -0,0 +1,633 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::FixedOffset;
+use chrono::Local;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use itertools::Itertools;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::EmlParseError;
+use super::timezones;
+use super::timezones::Observance;
+
+const PRODID: &str = "-//amail//notmuch-more//EN";
+
+/// An iCalendar (RFC 5545) object, as exchanged in iTIP (RFC 5546) messages.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Calendar {
+    /// The iTIP method, e.g. `REQUEST`, `REPLY` or `CANCEL`.
+    pub method: Option<String>,
+    pub events: Vec<CalEvent>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CalEvent {
+    pub uid: String,
+    pub sequence: u32,
+    /// When this copy was created, as parsed: `format` stamps its own.
+    pub dtstamp: Option<DateTime<FixedOffset>>,
+    /// Identifies the instance of a recurring event this is about, if not all.
+    pub recurrence_id: Option<CalTime>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub status: Option<String>,
+    pub organizer: Option<Attendee>,
+    pub attendees: Vec<Attendee>,
+    pub start: Option<CalTime>,
+    pub end: Option<CalTime>,
+    /// The recurrence rule as given, e.g. `FREQ=WEEKLY;BYDAY=MO`.
+    pub rrule: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Attendee {
+    pub address: String,
+    pub name: Option<String>,
+    /// Participation status, e.g. `NEEDS-ACTION`, `ACCEPTED` or `DECLINED`.
+    pub partstat: Option<String>,
+    pub role: Option<String>,
+    pub rsvp: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CalTime {
+    pub datetime: DateTime<FixedOffset>,
+    /// A `DATE` rather than `DATE-TIME`, i.e. all day.
+    pub all_day: bool,
+    pub tzid: Option<String>,
+    /// The TZID wasn't recognised, so the time was taken as local time.
+    #[serde(default)]
+    pub tz_unrecognised: bool,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartStat {
+    Accepted,
+    Tentative,
+    Declined,
+}
+
+impl PartStat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepted => "ACCEPTED",
+            Self::Tentative => "TENTATIVE",
+            Self::Declined => "DECLINED",
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Splits `s` on `sep`, except within double quotes.
+fn split_unquoted(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => (),
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn parse_property(line: &str) -> Option<Property> {
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+
+    let mut head = split_unquoted(&line[..colon], ';').into_iter();
+    Some(Property {
+        name: head.next()?.trim().to_uppercase(),
+        params: head
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.trim().to_uppercase(), v.trim_matches('"').into()))
+            .collect(),
+        value: line[colon + 1..].into(),
+    })
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => out.push('\n'),
+                Some(e) => out.push(e),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn parse_attendee(prop: &Property) -> Attendee {
+    let value = prop.value.trim();
+    Attendee {
+        address: match value.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => value[7..].into(),
+            _ => value.into(),
+        },
+        name: prop.param("CN").map(Into::into),
+        partstat: prop.param("PARTSTAT").map(str::to_uppercase),
+        role: prop.param("ROLE").map(str::to_uppercase),
+        rsvp: prop
+            .param("RSVP")
+            .is_some_and(|r| r.eq_ignore_ascii_case("TRUE")),
+    }
+}
+
+/// The observances of each `VTIMEZONE`, by TZID.
+type Zones = HashMap<String, Vec<Observance>>;
+
+/// Parses a UTC offset such as `-0800` or `+053000`.
+fn parse_offset(value: &str) -> Option<FixedOffset> {
+    let value = value.trim();
+    let (sign, digits) = match value.split_at_checked(1)? {
+        ("+", d) => (1, d),
+        ("-", d) => (-1, d),
+        _ => return None,
+    };
+    let field = |i: usize| {
+        digits
+            .get(i..i + 2)
+            .map_or(Some(0), |d| d.parse::<i32>().ok())
+    };
+    if digits.len() < 4 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (field(0)? * 3600 + field(2)? * 60 + field(4)?))
+}
+
+/// Collects the `VTIMEZONE`s of a calendar's (unfolded) lines, which may come
+/// after the events that use them.
+fn parse_zones<'a>(lines: impl Iterator<Item = &'a str>) -> Zones {
+    let mut zones = Zones::new();
+    let mut tzid: Option<String> = None;
+    let mut observance: Option<Observance> = None;
+
+    for prop in lines.filter_map(parse_property) {
+        let value = prop.value.trim();
+        match (prop.name.as_str(), &mut observance) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VTIMEZONE") => tzid = None,
+            ("BEGIN", None)
+                if ["STANDARD", "DAYLIGHT"].contains(&value.to_uppercase().as_str()) =>
+            {
+                observance = Some(Observance::default())
+            }
+            ("END", Some(_)) => {
+                if let (Some(tzid), Some(o)) = (&tzid, observance.take()) {
+                    zones.entry(tzid.clone()).or_default().push(o);
+                }
+            }
+            ("TZID", None) => tzid = Some(value.to_string()),
+            ("DTSTART", Some(o)) => {
+                o.start = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()
+            }
+            ("TZOFFSETTO", Some(o)) => o.offset = parse_offset(value),
+            ("RRULE", Some(o)) => o.rrule = Some(value.into()),
+            _ => (),
+        }
+    }
+    zones
+}
+
+/// Parses a `DATE` or `DATE-TIME`, in UTC, a TZID (IANA, Windows or defined
+/// in `zones`), or floating (local time).
+fn parse_time(prop: &Property, zones: &Zones) -> Option<CalTime> {
+    let value = prop.value.trim();
+    let tzid = prop.param("TZID").map(String::from);
+
+    if prop.param("VALUE") == Some("DATE") || value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some(CalTime {
+            datetime: date.and_hms_opt(0, 0, 0)?.and_utc().fixed_offset(),
+            all_day: true,
+            tzid,
+            tz_unrecognised: false,
+        });
+    }
+
+    let naive = NaiveDateTime::parse_from_str(value.trim_end_matches('Z'), "%Y%m%dT%H%M%S").ok()?;
+    let zoned = match (value.ends_with('Z'), &tzid) {
+        (true, _) => Some(naive.and_utc().fixed_offset()),
+        (false, Some(tzid)) => {
+            let zoned = timezones::in_zone(tzid, &naive, zones.get(tzid));
+            if zoned.is_none() {
+                println!("[WARN] Unrecognised TZID {tzid:?}, taken as local time");
+            }
+            zoned
+        }
+        (false, None) => None,
+    };
+    Some(CalTime {
+        tz_unrecognised: zoned.is_none() && tzid.is_some(),
+        datetime: match zoned {
+            Some(datetime) => datetime,
+            None => Local.from_local_datetime(&naive).earliest()?.fixed_offset(),
+        },
+        all_day: false,
+        tzid,
+    })
+}
+
+/// Parses a DURATION such as `PT1H30M`, `P1D` or `-P1W`.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(v) => (-1, v),
+        None => (1, value.trim_start_matches('+')),
+    };
+
+    let mut total = Duration::zero();
+    let mut digits = String::new();
+    for c in value.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            'T' => continue,
+            unit => {
+                let n = digits.parse::<i64>().ok()?;
+                digits.clear();
+                total += match unit {
+                    'W' => Duration::weeks(n),
+                    'D' => Duration::days(n),
+                    'H' => Duration::hours(n),
+                    'M' => Duration::minutes(n),
+                    'S' => Duration::seconds(n),
+                    _ => return None,
+                };
+            }
+        }
+    }
+    Some(total * sign)
+}
+
+pub fn parse_calendar(text: &str) -> Result<Calendar, EmlParseError> {
+    let unfolded = text
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let zones = parse_zones(unfolded.lines());
+    let mut calendar = Calendar::default();
+    let mut components: Vec<String> = vec![];
+    let mut event = CalEvent::default();
+    let mut duration = None;
+
+    for line in unfolded.lines().filter(|l| !l.trim().is_empty()) {
+        let prop = parse_property(line)
+            .ok_or_else(|| EmlParseError::new().within("text/calendar").reason(line))?;
+
+        match (prop.name.as_str(), components.last().map(String::as_str)) {
+            ("BEGIN", _) => {
+                components.push(prop.value.trim().to_uppercase());
+                if components.last().is_some_and(|c| c == "VEVENT") {
+                    event = CalEvent::default();
+                    duration = None;
+                }
+            }
+            ("END", Some(component)) => {
+                if component == "VEVENT" {
+                    if event.end.is_none()
+                        && let (Some(start), Some(d)) = (&event.start, duration)
+                    {
+                        event.end = Some(CalTime {
+                            datetime: start.datetime + d,
+                            ..start.clone()
+                        });
+                    }
+                    calendar.events.push(std::mem::take(&mut event));
+                }
+                components.pop();
+            }
+            ("METHOD", Some("VCALENDAR")) => {
+                calendar.method = Some(prop.value.trim().to_uppercase())
+            }
+            (name, Some("VEVENT")) => match name {
+                "UID" => event.uid = prop.value.trim().into(),
+                "SEQUENCE" => event.sequence = prop.value.trim().parse().unwrap_or(0),
+                "DTSTAMP" => event.dtstamp = parse_time(&prop, &zones).map(|t| t.datetime),
+                "RECURRENCE-ID" => event.recurrence_id = parse_time(&prop, &zones),
+                "SUMMARY" => event.summary = Some(unescape(&prop.value)),
+                "DESCRIPTION" => event.description = Some(unescape(&prop.value)),
+                "LOCATION" => event.location = Some(unescape(&prop.value)),
+                "STATUS" => event.status = Some(prop.value.trim().to_uppercase()),
+                "ORGANIZER" => event.organizer = Some(parse_attendee(&prop)),
+                "ATTENDEE" => event.attendees.push(parse_attendee(&prop)),
+                "DTSTART" => event.start = parse_time(&prop, &zones),
+                "DTEND" => event.end = parse_time(&prop, &zones),
+                "DURATION" => duration = parse_duration(&prop.value),
+                "RRULE" => event.rrule = Some(prop.value.trim().into()),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    match components.is_empty() {
+        true => Ok(calendar),
+        false => Err(EmlParseError::new()
+            .within("text/calendar")
+            .reason(&format!("Unterminated {}", components.join(", ")))),
+    }
+}
+
+/// Folds a content line to 75 octets, as RFC 5545 requires.
+fn fold(line: &str) -> String {
+    let mut out = String::new();
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            len = 1;
+        }
+        out.push(c);
+        len += c.len_utf8();
+    }
+    out.push_str("\r\n");
+    out
+}
+
+fn format_param(name: &str, value: &str) -> String {
+    match value.contains([':', ';', ',']) {
+        true => format!(";{name}=\"{}\"", value.replace('"', "'")),
+        false => format!(";{name}={value}"),
+    }
+}
+
+fn format_attendee(name: &str, attendee: &Attendee) -> String {
+    let mut line = name.to_string();
+    if let Some(role) = &attendee.role {
+        line.push_str(&format_param("ROLE", role));
+    }
+    if let Some(partstat) = &attendee.partstat {
+        line.push_str(&format_param("PARTSTAT", partstat));
+    }
+    if attendee.rsvp {
+        line.push_str(";RSVP=TRUE");
+    }
+    if let Some(cn) = &attendee.name {
+        line.push_str(&format_param("CN", cn));
+    }
+    format!("{line}:mailto:{}", attendee.address)
+}
+
+/// Times are written in UTC (or as dates), so no VTIMEZONEs are needed.
+fn format_time(name: &str, time: &CalTime) -> String {
+    match time.all_day {
+        true => format!("{name};VALUE=DATE:{}", time.datetime.format("%Y%m%d")),
+        false => format!(
+            "{name}:{}",
+            time.datetime.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")
+        ),
+    }
+}
+
+impl Calendar {
+    /// Formats as an iCalendar object, stamped with `now`.
+    pub fn format(&self, now: &DateTime<Utc>) -> String {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".into(),
+            format!("PRODID:{PRODID}"),
+        ];
+        if let Some(method) = &self.method {
+            lines.push(format!("METHOD:{method}"));
+        }
+
+        for event in &self.events {
+            lines.push("BEGIN:VEVENT".into());
+            lines.push(format!("UID:{}", event.uid));
+            lines.push(format!("SEQUENCE:{}", event.sequence));
+            lines.push(format!("DTSTAMP:{}", now.format("%Y%m%dT%H%M%SZ")));
+            if let Some(recurrence_id) = &event.recurrence_id {
+                lines.push(format_time("RECURRENCE-ID", recurrence_id));
+            }
+            if let Some(start) = &event.start {
+                lines.push(format_time("DTSTART", start));
+            }
+            if let Some(end) = &event.end {
+                lines.push(format_time("DTEND", end));
+            }
+            if let Some(rrule) = &event.rrule {
+                lines.push(format!("RRULE:{rrule}"));
+            }
+            for (name, value) in [
+                ("SUMMARY", &event.summary),
+                ("DESCRIPTION", &event.description),
+                ("LOCATION", &event.location),
+                ("STATUS", &event.status),
+            ] {
+                if let Some(value) = value {
+                    lines.push(format!("{name}:{}", escape(value)));
+                }
+            }
+            if let Some(organizer) = &event.organizer {
+                lines.push(format_attendee("ORGANIZER", organizer));
+            }
+            for attendee in &event.attendees {
+                lines.push(format_attendee("ATTENDEE", attendee));
+            }
+            lines.push("END:VEVENT".into());
+        }
+
+        lines.push("END:VCALENDAR".into());
+        lines.iter().map(|l| fold(l)).join("")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVITE: &str = "BEGIN:VCALENDAR\r\n\
+        VERSION:2.0\r\n\
+        METHOD:REQUEST\r\n\
+        BEGIN:VTIMEZONE\r\n\
+        TZID:Europe/London\r\n\
+        BEGIN:STANDARD\r\n\
+        DTSTART:19701025T020000\r\n\
+        END:STANDARD\r\n\
+        END:VTIMEZONE\r\n\
+        BEGIN:VEVENT\r\n\
+        UID:abc-123@example.com\r\n\
+        SEQUENCE:2\r\n\
+        DTSTAMP:20260601T090000Z\r\n\
+        SUMMARY:Planning\\, Q3\r\n\
+        DESCRIPTION:Agenda:\\n1. Budget\r\n\
+        ORGANIZER;CN=\"Boss: Jane\":mailto:jane@example.com\r\n\
+        ATTENDEE;ROLE=REQ-PARTICIPANT;PARTSTAT=NEEDS-ACTION;RSVP=TRUE;CN=Me:MAILTO:\r\n \
+         me@example.org\r\n\
+        DTSTART;TZID=Europe/London:20260701T100000\r\n\
+        DURATION:PT1H30M\r\n\
+        RRULE:FREQ=WEEKLY;COUNT=4\r\n\
+        BEGIN:VALARM\r\n\
+        DESCRIPTION:Reminder\r\n\
+        END:VALARM\r\n\
+        END:VEVENT\r\n\
+        END:VCALENDAR\r\n";
+
+    #[test]
+    fn parse_invite() {
+        let calendar = parse_calendar(INVITE).unwrap();
+        let event = &calendar.events[0];
+
+        assert_eq!(calendar.method.as_deref(), Some("REQUEST"));
+        assert_eq!(event.uid, "abc-123@example.com");
+        assert_eq!(event.sequence, 2);
+        assert_eq!(event.dtstamp.unwrap().timestamp(), 1780304400);
+        assert_eq!(event.summary.as_deref(), Some("Planning, Q3"));
+        assert_eq!(event.description.as_deref(), Some("Agenda:\n1. Budget"));
+        assert_eq!(event.rrule.as_deref(), Some("FREQ=WEEKLY;COUNT=4"));
+        assert_eq!(
+            event.organizer.as_ref().unwrap().name.as_deref(),
+            Some("Boss: Jane")
+        );
+        assert_eq!(
+            event.attendees,
+            vec![Attendee {
+                address: "me@example.org".into(),
+                name: Some("Me".into()),
+                partstat: Some("NEEDS-ACTION".into()),
+                role: Some("REQ-PARTICIPANT".into()),
+                rsvp: true,
+            }]
+        );
+
+        let start = event.start.as_ref().unwrap();
+        assert_eq!(start.datetime.to_rfc3339(), "2026-07-01T10:00:00+01:00");
+        assert_eq!(start.tzid.as_deref(), Some("Europe/London"));
+        assert_eq!(
+            event.end.as_ref().unwrap().datetime.to_rfc3339(),
+            "2026-07-01T11:30:00+01:00"
+        );
+    }
+
+    #[test]
+    fn parse_zones_windows_and_vtimezone() {
+        let calendar = parse_calendar(
+            "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\n\
+            DTSTART;TZID=W. Europe Standard Time:20260115T090000\r\n\
+            DTEND;TZID=\"Custom Zone\":20260715T090000\r\n\
+            RECURRENCE-ID;TZID=Nowhere:20260115T090000\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VTIMEZONE\r\n\
+            TZID:Custom Zone\r\n\
+            BEGIN:STANDARD\r\n\
+            DTSTART:16011028T030000\r\n\
+            TZOFFSETTO:+0530\r\n\
+            RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=10\r\n\
+            END:STANDARD\r\n\
+            BEGIN:DAYLIGHT\r\n\
+            DTSTART:16010325T020000\r\n\
+            TZOFFSETTO:+0630\r\n\
+            RRULE:FREQ=YEARLY;BYDAY=-1SU;BYMONTH=3\r\n\
+            END:DAYLIGHT\r\n\
+            END:VTIMEZONE\r\n\
+            END:VCALENDAR\r\n",
+        )
+        .unwrap();
+        let event = &calendar.events[0];
+
+        assert_eq!(
+            event.start.as_ref().unwrap().datetime.to_rfc3339(),
+            "2026-01-15T09:00:00+01:00"
+        );
+        assert_eq!(
+            event.end.as_ref().unwrap().datetime.to_rfc3339(),
+            "2026-07-15T09:00:00+06:30"
+        );
+        assert!(!event.end.as_ref().unwrap().tz_unrecognised);
+        assert!(event.recurrence_id.as_ref().unwrap().tz_unrecognised);
+    }
+
+    #[test]
+    fn parse_all_day_utc() {
+        let calendar = parse_calendar(
+            "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART;VALUE=DATE:20261224\nDTEND:20261225T120000Z\nEND:VEVENT\nEND:VCALENDAR\n",
+        )
+        .unwrap();
+        let event = &calendar.events[0];
+
+        assert!(event.start.as_ref().unwrap().all_day);
+        assert_eq!(
+            event.end.as_ref().unwrap().datetime.to_rfc3339(),
+            "2026-12-25T12:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn unterminated() {
+        assert!(parse_calendar("BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\n").is_err());
+    }
+
+    #[test]
+    fn format_round_trip() {
+        let mut calendar = parse_calendar(INVITE).unwrap();
+        calendar.method = Some("REPLY".into());
+        calendar.events[0].summary = Some(format!("{}; with a long title", "x".repeat(80)));
+        let formatted = calendar.format(&Utc::now());
+
+        assert!(formatted.split("\r\n").all(|l| l.len() <= 75));
+        assert!(formatted.contains("DTSTART:20260701T090000Z\r\n"));
+        assert!(formatted.contains("ORGANIZER;CN=\"Boss: Jane\":mailto:jane@example.com\r\n"));
+
+        let parsed = parse_calendar(&formatted).unwrap();
+        assert_eq!(parsed.method.as_deref(), Some("REPLY"));
+        assert_eq!(parsed.events[0].summary, calendar.events[0].summary);
+        assert_eq!(parsed.events[0].attendees, calendar.events[0].attendees);
+        assert_eq!(
+            parsed.events[0].end,
+            calendar.events[0]
+                .end
+                .clone()
+                .map(|e| CalTime { tzid: None, ..e })
+        );
+    }
+}
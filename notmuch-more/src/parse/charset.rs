@@ -0,0 +1,187 @@
+use encoding_rs::BIG5;
+use encoding_rs::EUC_JP;
+use encoding_rs::EUC_KR;
+use encoding_rs::Encoding;
+use encoding_rs::GBK;
+use encoding_rs::SHIFT_JIS;
+use encoding_rs::UTF_8;
+use encoding_rs::WINDOWS_1251;
+use encoding_rs::WINDOWS_1252;
+
+/// Decodes text in its declared charset, or if that's missing or the text
+/// isn't valid in it, in the most plausible of some common charsets. Returns
+/// the text and the charset actually used.
+///
+/// Labels are as per the WHATWG Encoding Standard, so e.g. ISO-8859-1 is
+/// decoded as its superset Windows-1252, and GB2312 as GBK.
+pub(crate) fn decode(raw: &[u8], declared: Option<&str>) -> (String, &'static str) {
+    let encoding = declared.and_then(|l| Encoding::for_label(l.trim().as_bytes()));
+    let ascii_label =
+        declared.is_some_and(|l| matches!(l.trim().to_lowercase().as_str(), "us-ascii" | "ascii"));
+
+    if raw.is_ascii() && encoding.is_none_or(Encoding::is_ascii_compatible) {
+        let charset = match encoding {
+            Some(e) if !ascii_label => e.name(),
+            _ => "US-ASCII",
+        };
+        return (String::from_utf8_lossy(raw).into(), charset);
+    }
+
+    // 8-bit content can't be ASCII, so that label is as good as none. And as
+    // anything decodes as Windows-1252, that's left to detection, which
+    // prefers it unless something else is more plausible.
+    if let Some(e) = encoding.filter(|&e| !ascii_label && e != WINDOWS_1252) {
+        if let Some(text) = e.decode_without_bom_handling_and_without_replacement(raw) {
+            return (text.into(), e.name());
+        }
+        println!("[WARN] Content isn't valid {}, detecting charset", e.name());
+    }
+    detect(raw)
+}
+
+/// The encoding of a Windows codepage, as TNEF and RTF give them, defaulting
+/// to Windows-1252.
+pub(crate) fn for_codepage(codepage: u32) -> &'static Encoding {
+    let label = match codepage {
+        65001 => "utf-8".into(),
+        932 => "shift_jis".into(),
+        936 => "gbk".into(),
+        949 => "euc-kr".into(),
+        950 => "big5".into(),
+        20932 => "euc-jp".into(),
+        20866 => "koi8-r".into(),
+        21866 => "koi8-u".into(),
+        866 => "ibm866".into(),
+        10000 => "macintosh".into(),
+        28591..=28606 => format!("iso-8859-{}", codepage - 28590),
+        _ => format!("windows-{codepage}"),
+    };
+    Encoding::for_label(label.as_bytes()).unwrap_or(WINDOWS_1252)
+}
+
+fn detect(raw: &[u8]) -> (String, &'static str) {
+    if let Ok(text) = std::str::from_utf8(raw) {
+        return (text.into(), UTF_8.name());
+    }
+
+    // In order of preference, should they be as plausible as each other.
+    [
+        WINDOWS_1252,
+        GBK,
+        SHIFT_JIS,
+        EUC_JP,
+        BIG5,
+        EUC_KR,
+        WINDOWS_1251,
+    ]
+    .into_iter()
+    .enumerate()
+    .filter_map(|(i, e)| {
+        let text = e.decode_without_bom_handling_and_without_replacement(raw)?;
+        Some((plausibility(&text, e), i, text, e))
+    })
+    .max_by_key(|(score, i, _, _)| (*score, std::cmp::Reverse(*i)))
+    .map(|(_, _, text, e)| (text.into(), e.name()))
+    .unwrap_or_else(|| (String::from_utf8_lossy(raw).into(), UTF_8.name()))
+}
+
+fn is_latin_letter(c: char) -> bool {
+    matches!(c, 'À'..='ÿ' | '\u{100}'..='\u{17F}') && c != '×' && c != '÷'
+}
+
+/// How plausible text decoded as `encoding` is as real text: mojibake tends
+/// to have runs of accented letters, CJK or Cyrillic mixed into Latin words,
+/// Cyrillic capitals mid-word, control or private use characters, and stray
+/// symbols.
+fn plausibility(text: &str, encoding: &Encoding) -> i64 {
+    let japanese = encoding == SHIFT_JIS || encoding == EUC_JP;
+    let chars = text.chars().collect::<Vec<_>>();
+
+    (0..chars.len())
+        .map(|i| {
+            let prev = i.checked_sub(1).map(|p| chars[p]);
+            let next = chars.get(i + 1).copied();
+            match chars[i] {
+                c if c.is_ascii() => 0,
+                '\u{FFFD}' | '\u{80}'..='\u{9F}' | '\u{E000}'..='\u{F8FF}' => -5,
+                c if is_latin_letter(c) => {
+                    match prev.is_some_and(|p| !p.is_ascii() && p.is_alphabetic()) {
+                        true => -1,
+                        false => 1,
+                    }
+                }
+                c @ 'Ѐ'..='ӿ' => {
+                    let mixed_script = [prev, next].iter().flatten().any(char::is_ascii_alphabetic);
+                    let mid_word_capital =
+                        c.is_uppercase() && prev.is_some_and(char::is_alphabetic);
+                    match (mixed_script || mid_word_capital, ('А'..='я').contains(&c)) {
+                        (true, _) => -1,
+                        (false, true) => 1,
+                        (false, false) => 0,
+                    }
+                }
+                '\u{3040}'..='\u{30FF}' => match japanese {
+                    true => 2,
+                    false => 0,
+                },
+                // Where a double-byte decoding has eaten a letter of a word.
+                '\u{4E00}'..='\u{9FFF}' | '\u{AC00}'..='\u{D7A3}'
+                    if [prev, next].iter().flatten().any(char::is_ascii_alphabetic) =>
+                {
+                    0
+                }
+                '\u{4E00}'..='\u{9FFF}'
+                | '\u{AC00}'..='\u{D7A3}'
+                | '\u{3000}'..='\u{303F}'
+                | '\u{FF01}'..='\u{FF5E}' => 1,
+                '\u{A0}' | '£' | '©' | '°' | '€' | '–' | '—' | '‘'..='”' | '…' => 0,
+                _ => -1,
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declared_charset_used() {
+        assert_eq!(
+            decode(b"\x93Caf\xe9\x94", Some("ISO-8859-1")),
+            ("“Café”".into(), "windows-1252")
+        );
+        assert_eq!(
+            decode(&GBK.encode("你好").0, Some("gb2312")),
+            ("你好".into(), "GBK")
+        );
+        assert_eq!(decode(b"Hi", None), ("Hi".into(), "US-ASCII"));
+        assert_eq!(decode(b"Hi", Some("utf-8")), ("Hi".into(), "UTF-8"));
+        assert_eq!(decode(b"Hi", Some("US-ASCII")), ("Hi".into(), "US-ASCII"));
+    }
+
+    #[test]
+    fn wrong_or_missing_charset_detected() {
+        assert_eq!(
+            decode(b"caf\xe9", Some("utf-8")),
+            ("café".into(), "windows-1252")
+        );
+        assert_eq!(
+            decode(&SHIFT_JIS.encode("こんにちは、世界").0, None),
+            ("こんにちは、世界".into(), "Shift_JIS")
+        );
+        assert_eq!(
+            decode(&GBK.encode("你好，世界").0, Some("us-ascii")),
+            ("你好，世界".into(), "GBK")
+        );
+        assert_eq!(
+            decode(&SHIFT_JIS.encode("こんにちは").0, Some("iso-8859-1")),
+            ("こんにちは".into(), "Shift_JIS")
+        );
+        assert_eq!(
+            decode(&WINDOWS_1251.encode("Привет, мир").0, None),
+            ("Привет, мир".into(), "windows-1251")
+        );
+        assert_eq!(decode("Grüße".as_bytes(), None), ("Grüße".into(), "UTF-8"));
+    }
+}
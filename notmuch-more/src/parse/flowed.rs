@@ -0,0 +1,82 @@
+use itertools::Itertools;
+
+/// Rejoins RFC 3676 `format=flowed` soft-broken lines, so that text reflows to
+/// the reader's width rather than keeping the sender's line breaks.
+pub(crate) fn unflow(content: &str, delsp: bool) -> String {
+    let mut lines: Vec<(usize, String)> = vec![];
+    let mut flowing = false;
+
+    for line in content
+        .split('\n')
+        .map(|l| l.strip_suffix('\r').unwrap_or(l))
+    {
+        let depth = line.chars().take_while(|&c| c == '>').count();
+        let text = &line[depth..];
+        let text = text.strip_prefix(' ').unwrap_or(text);
+
+        let soft_break = text.ends_with(' ') && text != "-- ";
+        let text = match soft_break && delsp {
+            true => &text[..text.len() - 1],
+            false => text,
+        };
+
+        match lines.last_mut() {
+            Some((d, acc)) if flowing && *d == depth => acc.push_str(text),
+            // A change in quote depth is a hard break, even after a soft one.
+            Some((_, acc)) if flowing => {
+                acc.truncate(acc.trim_end().len());
+                lines.push((depth, text.into()))
+            }
+            _ => lines.push((depth, text.into())),
+        }
+        flowing = soft_break;
+    }
+
+    lines
+        .into_iter()
+        .map(|(depth, text)| match (depth, text.is_empty()) {
+            (0, _) => text,
+            (_, true) => ">".repeat(depth),
+            (_, false) => format!("{} {text}", ">".repeat(depth)),
+        })
+        .join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_breaks_joined() {
+        assert_eq!(
+            unflow("one two \r\nthree\r\n\r\nfour", false),
+            "one two three\r\n\r\nfour"
+        );
+    }
+
+    #[test]
+    fn delsp_removes_soft_break_space() {
+        assert_eq!(unflow("sp \r\nlit\r\n", true), "split\r\n");
+    }
+
+    #[test]
+    fn stuffing_removed() {
+        assert_eq!(
+            unflow(" From here\r\n  indented", false),
+            "From here\r\n indented"
+        );
+    }
+
+    #[test]
+    fn signature_separator_not_flowed() {
+        assert_eq!(unflow("bye\r\n-- \r\nme", false), "bye\r\n-- \r\nme");
+    }
+
+    #[test]
+    fn quote_depth_change_is_hard_break() {
+        assert_eq!(
+            unflow(">> deep \r\n>> er\r\n> shallow \r\nunquoted", false),
+            ">> deep er\r\n> shallow\r\nunquoted"
+        );
+    }
+}
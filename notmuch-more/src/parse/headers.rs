@@ -1,33 +1,89 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
 
 use chrono::DateTime;
 use chrono::FixedOffset;
+use chrono::Local;
+use chrono::Offset;
 use chrono::TimeZone;
 use chrono::Utc;
 use delegate::delegate;
 use itertools::Itertools;
 use notmuch::Message;
-use regex::Regex;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
 use serde::Deserialize;
 use serde::Serialize;
 
+use super::AuthResults;
 use super::EmlAddr;
 use super::EmlParseError;
 use super::Mailbox;
+use super::MailingList;
 use super::addresses::parse_address_header;
 use super::addresses::parse_optional_address_list_header;
+use super::auth_results::parse_arc_results;
+use super::auth_results::parse_auth_results;
 use super::parse_address;
+use super::received::delivery_hop;
+use super::received::strip_comments;
+
+/// How far `Date` may be after, or before, a message's delivery until it's
+/// suspect: a little clock skew, or some days queued on the way.
+const MAX_DATE_AHEAD: i64 = 2 * 3600;
+const MAX_DATE_BEHIND: i64 = 7 * 24 * 3600;
+
+fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc2822(strip_comments(value).trim()).ok()
+}
+
+fn date_inconsistent(date: i64, received: i64) -> bool {
+    date - received > MAX_DATE_AHEAD || received - date > MAX_DATE_BEHIND
+}
+
+/// A new, globally unique Message-ID, `<timestamp.random@domain>` with the
+/// domain taken from the sender's address.
+pub fn generate_message_id(sender: &str) -> String {
+    let domain = sender
+        .rsplit_once('@')
+        .map(|(_, d)| d.trim_end_matches('>'))
+        .filter(|d| !d.is_empty())
+        .unwrap_or("localhost");
+    format!(
+        "<{}.{}@{domain}>",
+        Utc::now().timestamp_millis(),
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect::<String>()
+    )
+}
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct EmlMeta {
+    /// Recorded by the last server it was forwarded through, if any:
+    /// unverified, so not to be relied on.
+    pub arc_results: Vec<AuthResults>,
+    /// From the last server to record any, if it's trusted.
+    pub auth_results: Vec<AuthResults>,
     pub cc: Option<Vec<EmlAddr>>,
     pub bcc: Option<Vec<EmlAddr>>,
+    /// The `Date` header, in the sender's own offset.
+    pub date: Option<DateTime<FixedOffset>>,
+    /// Whether `date` is far from when the message was delivered, as from a
+    /// wrong clock or a forgery.
+    pub date_inconsistent: bool,
     pub from: Vec<Mailbox>,
     pub id: String,
     pub id_thread: String,
     pub in_reply_to: Option<String>,
+    pub list: Option<MailingList>,
     pub received_by: Option<Mailbox>,
     pub references: Option<String>,
     pub reply_to: Option<Vec<EmlAddr>>,
@@ -50,6 +106,18 @@ impl EmlMeta {
     pub fn resolve_sender(&self) -> Result<String, EmlParseError> {
         Rfc5322Fields::from(self).resolve_sender()
     }
+
+    /// The sender's UTC offset when they sent it, else ours at the time.
+    pub fn sender_offset(&self) -> FixedOffset {
+        match self.date {
+            Some(date) => *date.offset(),
+            None => Local
+                .timestamp_opt(self.timestamp, 0)
+                .single()
+                .map(|d| d.offset().fix())
+                .unwrap_or_else(|| Utc.fix()),
+        }
+    }
 }
 
 pub(crate) fn parse_header(eml: &Message, header: &str) -> Result<Option<String>, EmlParseError> {
@@ -69,15 +137,84 @@ pub(crate) fn must_parse_header(eml: &Message, header: &str) -> Result<String, E
     }
 }
 
+/// The raw header section of the message at `path`, as `(name, value)` in
+/// order: notmuch only gives the first of a repeated header.
+pub(crate) fn read_raw_headers(path: impl AsRef<Path>) -> std::io::Result<Vec<(String, String)>> {
+    let mut block = vec![];
+    for line in BufReader::new(File::open(path)?).split(b'\n') {
+        let line = line?;
+        if line.iter().all(|b| b.is_ascii_whitespace()) {
+            break;
+        }
+        block.extend(line);
+        block.push(b'\n');
+    }
+
+    Ok(mailparse::parse_headers(&block)
+        .map(|(headers, _)| {
+            headers
+                .iter()
+                .map(|h| (h.get_key(), h.get_value()))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
 impl TryFrom<&Message> for EmlMeta {
     type Error = EmlParseError;
 
+    /// Without any authentication results, none being trusted.
     fn try_from(eml: &Message) -> Result<Self, Self::Error> {
+        Self::parse(eml, &[])
+    }
+}
+
+impl EmlMeta {
+    /// Parses `eml`, with the authentication results of the
+    /// `trusted_authserv_ids` (see `parse_auth_results`).
+    pub(crate) fn parse(
+        eml: &Message,
+        trusted_authserv_ids: &[String],
+    ) -> Result<Self, EmlParseError> {
+        // As notmuch gives them, having read the file anyway, rather than
+        // reading it again for every message listed: just the first of any
+        // repeated, i.e. the most recent.
+        let headers = [
+            "ARC-Authentication-Results",
+            "Authentication-Results",
+            "List-Archive",
+            "List-Id",
+            "List-Post",
+            "List-Unsubscribe",
+            "List-Unsubscribe-Post",
+        ]
+        .into_iter()
+        .filter_map(|name| {
+            parse_header(eml, name)
+                .transpose()
+                .map(|v| v.map(|v| (name.to_string(), v)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+        let delivery = parse_header(eml, "Received")?
+            .map(|r| delivery_hop(&r))
+            .unwrap_or_default();
+        let date = parse_header(eml, "Date")?.and_then(|d| parse_date(&d));
+
         Ok(EmlMeta {
+            arc_results: parse_arc_results(&headers),
+
+            auth_results: parse_auth_results(&headers, trusted_authserv_ids),
+
             bcc: parse_optional_address_list_header(eml, "Bcc")?,
 
             cc: parse_optional_address_list_header(eml, "Cc")?,
 
+            date_inconsistent: date
+                .zip(delivery.timestamp)
+                .is_some_and(|(d, received)| date_inconsistent(d.timestamp(), received)),
+
+            date,
+
             from: parse_address_header(eml, &must_parse_header(eml, "From")?)?
                 .iter()
                 .map(Mailbox::try_from)
@@ -89,23 +226,12 @@ impl TryFrom<&Message> for EmlMeta {
 
             in_reply_to: parse_header(eml, "In-Reply-To")?,
 
-            received_by: match parse_header(eml, "Received")? {
-                Some(h) => {
-                    let rx = Regex::new(r"for ([^\s]+@[^\s]+\.[^\s]+);")
-                        .map_err(|e| {
-                            EmlParseError::from(eml)
-                                .reason(&format!("Building `Received` regex: {e}"))
-                        })?
-                        .captures(&h)
-                        .map(|m| m.get(1).unwrap().as_str());
-
-                    match rx {
-                        Some(a) => parse_address_header(eml, a)
-                            .map(|a| a.extract_single_info())?
-                            .map(|s| Mailbox::from(&s)),
-                        None => None,
-                    }
-                }
+            list: MailingList::from_headers(&headers),
+
+            received_by: match delivery.for_ {
+                Some(a) => parse_address_header(eml, &a)
+                    .map(|a| a.extract_single_info())?
+                    .map(|s| Mailbox::from(&s)),
                 None => None,
             },
 
@@ -223,31 +349,6 @@ impl Rfc5322Fields {
             .join("\r\n")
     }
 
-    pub fn format_message(&self, body: &str, boundary: &str) -> String {
-        format!(
-            "{}\r\nContent-Type: multipart/mixed; boundary={boundary}\r\n\r\n{body}\r\n--{boundary}--",
-            self.format_fields(),
-        )
-    }
-
-    pub fn format_message_id_for_destination(&self, dest: &str) -> String {
-        if let Some(curr) = self.get("Message-ID")
-            && let Ok(re) = Regex::new(r"^<(?P<t>.*)\.(?P<id>.*)\.(?P<d>.*)>$")
-        {
-            return re.replace(curr, format!("<$t.$id.{dest}>")).into();
-        }
-
-        let dt = self
-            .get("Date")
-            .map(|d| {
-                DateTime::parse_from_rfc2822(d).unwrap_or_else(|_| {
-                    Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap())
-                })
-            })
-            .unwrap_or_else(|| Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap()));
-        format!("<{}.unknown.{dest}>", dt.timestamp())
-    }
-
     pub fn resolve_reply_to(&self) -> Result<String, EmlParseError> {
         if let Some(reply_to) = self.get("Reply-To") {
             return Ok(reply_to.clone());
@@ -343,17 +444,11 @@ impl From<&EmlMeta> for Rfc5322Fields {
         }
 
         fields.from_addr(&meta.from);
-        fields.message_id(&fields.format_message_id_for_destination(&{
-            let maybe_dests = fields.destinations();
-            match maybe_dests {
-                Ok(dests) => match dests.len() {
-                    0 => "@unknown".into(),
-                    1 => dests[0].clone(),
-                    _ => "@multiple".into(),
-                },
-                _ => "@unknown".into(),
-            }
-        }));
+        // Kept if the message already has one, e.g. if it's being re-sent.
+        fields.message_id(&match meta.id.is_empty() {
+            true => generate_message_id(meta.from.first().map_or("", |m| &m.address)),
+            false => format!("<{}>", meta.id),
+        });
 
         fields.date(&Utc.timestamp_opt(meta.timestamp, 0).unwrap());
 
@@ -366,6 +461,8 @@ impl TryInto<EmlMeta> for Rfc5322Fields {
 
     fn try_into(self) -> Result<EmlMeta, Self::Error> {
         Ok(EmlMeta {
+            arc_results: vec![],
+            auth_results: vec![],
             bcc: match self.get("Bcc") {
                 Some(bcc) => Some(
                     mailparse::addrparse(bcc)
@@ -386,6 +483,8 @@ impl TryInto<EmlMeta> for Rfc5322Fields {
                 ),
                 _ => None,
             },
+            date: self.get("Date").and_then(|d| parse_date(d)),
+            date_inconsistent: false,
             from: match self.get("From") {
                 Some(from) => Ok(mailparse::addrparse(from)
                     .map_err(|e| Self::Error::new().within("From").reason(&e.to_string()))?
@@ -394,9 +493,18 @@ impl TryInto<EmlMeta> for Rfc5322Fields {
                     .collect::<Result<_, _>>()?),
                 _ => Err(Self::Error::new().within("From").reason("Missing header")),
             }?,
-            id: "".into(),
+            id: self
+                .get("Message-ID")
+                .map(|id| {
+                    id.trim()
+                        .trim_start_matches('<')
+                        .trim_end_matches('>')
+                        .into()
+                })
+                .unwrap_or_default(),
             id_thread: "".into(),
             in_reply_to: self.get("In-Reply-To").cloned(),
+            list: None,
             received_by: None,
             references: self.get("References").cloned(),
             reply_to: {
@@ -475,4 +583,78 @@ mod tests {
             "Bcc:\r\nTo: bar@foo.com",
         )
     }
+
+    #[test]
+    fn generated_message_ids_unique() {
+        let a = generate_message_id("Me <me@example.com>");
+        let b = generate_message_id("me@example.com");
+
+        assert_ne!(a, b);
+        assert!(a.starts_with('<') && a.ends_with("@example.com>"));
+        assert!(generate_message_id("").ends_with("@localhost>"));
+    }
+
+    #[test]
+    fn message_id_round_trip() {
+        let mut meta = EmlMeta {
+            from: vec![Mailbox {
+                name: "Me".into(),
+                address: "me@example.com".into(),
+            }],
+            to: Some(vec![]),
+            ..Default::default()
+        };
+
+        let generated: EmlMeta = Rfc5322Fields::from(&meta).try_into().unwrap();
+        assert!(generated.id.ends_with("@example.com"));
+        assert!(!generated.id.contains('<'));
+
+        meta.id = "kept@example.org".into();
+        assert_eq!(
+            Rfc5322Fields::from(&meta).get("Message-ID"),
+            Some(&"<kept@example.org>".to_string())
+        );
+    }
+
+    #[test]
+    fn date_kept_in_sender_offset() {
+        let date = parse_date("Tue, 2 Jan 2024 10:00:30 -0800 (PST)").unwrap();
+        assert_eq!(date.to_rfc2822(), "Tue, 2 Jan 2024 10:00:30 -0800");
+
+        let meta = EmlMeta {
+            date: Some(date),
+            timestamp: date.timestamp(),
+            ..Default::default()
+        };
+        assert_eq!(
+            meta.sender_offset(),
+            FixedOffset::west_opt(8 * 3600).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&meta).unwrap()["date"],
+            "2024-01-02T10:00:30-08:00"
+        );
+
+        let fields: EmlMeta = Rfc5322Fields::from([
+            ("From".into(), "me@example.com".into()),
+            ("Date".into(), "Wed, 3 Jan 2024 09:00:00 +0530".into()),
+        ])
+        .try_into()
+        .unwrap();
+        assert_eq!(
+            fields.sender_offset(),
+            FixedOffset::east_opt(19800).unwrap()
+        );
+    }
+
+    #[test]
+    fn inconsistent_dates_flagged() {
+        let received = 1704218400;
+
+        assert!(!date_inconsistent(received - 30, received));
+        assert!(!date_inconsistent(received + 600, received));
+        assert!(!date_inconsistent(received - 3 * 24 * 3600, received));
+        assert!(date_inconsistent(received + 24 * 3600, received));
+        assert!(date_inconsistent(received - 30 * 24 * 3600, received));
+    }
 }
@@ -0,0 +1,300 @@
+use html5ever::parse_document;
+use html5ever::tendril::TendrilSink;
+use itertools::Itertools;
+use markup5ever_rcdom::Handle;
+use markup5ever_rcdom::NodeData;
+use markup5ever_rcdom::RcDom;
+use regex::Regex;
+
+/// Renders HTML as plain text: blocks are separated by blank lines, lists are
+/// bulleted or numbered, blockquotes are `>` quoted, tables are laid out in
+/// columns, and links are kept as numbered footnotes.
+pub fn html_to_text(html: &str) -> String {
+    let dom = parse_document(RcDom::default(), Default::default()).one(html);
+    let mut renderer = Renderer::default();
+    let mut text = normalise(&renderer.render(&dom.document));
+
+    if !renderer.links.is_empty() {
+        text.push_str("\n\n");
+        text.push_str(
+            &renderer
+                .links
+                .iter()
+                .enumerate()
+                .map(|(i, l)| format!("[{}] {l}", i + 1))
+                .join("\n"),
+        );
+    }
+
+    text.replace('\n', "\r\n")
+}
+
+#[derive(Default)]
+struct Renderer {
+    links: Vec<String>,
+}
+
+impl Renderer {
+    fn render(&mut self, node: &Handle) -> String {
+        match &node.data {
+            NodeData::Document => self.children(node),
+            NodeData::Text { contents } => Regex::new(r"\s+")
+                .unwrap()
+                .replace_all(&contents.borrow(), " ")
+                .into(),
+            NodeData::Element { name, .. } => match &*name.local {
+                "head" | "script" | "style" | "template" | "title" => String::new(),
+                "br" => "\n".into(),
+                "hr" => block(&"-".repeat(20)),
+                "a" => self.link(node),
+                "img" => attr(node, "alt")
+                    .filter(|a| !a.trim().is_empty())
+                    .map(|a| format!("[{}]", a.trim()))
+                    .unwrap_or_default(),
+                "pre" => block(&raw_text(node)),
+                "blockquote" => block(
+                    &normalise(&self.children(node))
+                        .split('\n')
+                        .map(|l| match l.starts_with('>') || l.is_empty() {
+                            true => format!(">{l}"),
+                            false => format!("> {l}"),
+                        })
+                        .join("\n"),
+                ),
+                "ul" => block(&self.list(node, false)),
+                "ol" => block(&self.list(node, true)),
+                "table" => block(&self.table(node)),
+                "address" | "article" | "aside" | "center" | "dd" | "div" | "dl" | "dt"
+                | "figure" | "footer" | "form" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+                | "header" | "main" | "nav" | "p" | "section" => block(&self.children(node)),
+                _ => self.children(node),
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn children(&mut self, node: &Handle) -> String {
+        let mut out = String::new();
+        for child in node.children.borrow().iter() {
+            let text = self.render(child);
+            // Whitespace is collapsed across (as well as within) text nodes.
+            match out.is_empty() || out.ends_with([' ', '\n']) {
+                true => out.push_str(text.trim_start_matches(' ')),
+                false => out.push_str(&text),
+            }
+        }
+        out
+    }
+
+    fn link(&mut self, node: &Handle) -> String {
+        let text = self.children(node);
+        let href = match attr(node, "href") {
+            Some(href) => href.trim().to_string(),
+            None => return text,
+        };
+
+        let visible = text.trim();
+        let redundant = href.starts_with('#')
+            || href.starts_with("javascript:")
+            || [
+                href.as_str(),
+                href.trim_start_matches("mailto:"),
+                href.trim_start_matches("https://"),
+                href.trim_start_matches("http://"),
+            ]
+            .iter()
+            .any(|h| h.trim_end_matches('/') == visible.trim_end_matches('/'));
+        if redundant {
+            return text;
+        }
+
+        let n = match self.links.iter().position(|l| *l == href) {
+            Some(i) => i + 1,
+            None => {
+                self.links.push(href);
+                self.links.len()
+            }
+        };
+        format!("{text}[{n}]")
+    }
+
+    fn list(&mut self, node: &Handle, ordered: bool) -> String {
+        node.children
+            .borrow()
+            .iter()
+            .filter(|c| is_element(c, &["li"]))
+            .enumerate()
+            .map(|(i, li)| {
+                let marker = match ordered {
+                    true => format!("{}. ", i + 1),
+                    false => "* ".into(),
+                };
+                let indent = " ".repeat(marker.len());
+                let item = Regex::new(r"\n{2,}")
+                    .unwrap()
+                    .replace_all(&normalise(&self.children(li)), "\n")
+                    .split('\n')
+                    .map(|l| match l.is_empty() {
+                        true => String::new(),
+                        false => format!("{indent}{l}"),
+                    })
+                    .join("\n");
+                format!("{marker}{}", &item[indent.len().min(item.len())..])
+            })
+            .join("\n")
+    }
+
+    fn table(&mut self, node: &Handle) -> String {
+        let mut rows: Vec<Vec<String>> = vec![];
+        let sections = node.children.borrow().clone();
+        for section in sections {
+            let trs = match is_element(&section, &["thead", "tbody", "tfoot"]) {
+                true => section.children.borrow().clone(),
+                false => vec![section],
+            };
+            for tr in trs.iter().filter(|c| is_element(c, &["tr"])) {
+                rows.push(
+                    tr.children
+                        .borrow()
+                        .iter()
+                        .filter(|c| is_element(c, &["td", "th"]))
+                        .map(|cell| normalise(&self.children(cell)).replace('\n', " "))
+                        .collect(),
+                );
+            }
+        }
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths = (0..columns)
+            .map(|c| {
+                rows.iter()
+                    .filter_map(|r| r.get(c))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        // Only worth aligning if the rows will actually fit on a line.
+        let align = widths.iter().sum::<usize>() + 3 * columns.saturating_sub(1) <= 78;
+
+        rows.iter()
+            .map(|r| {
+                r.iter()
+                    .zip(&widths)
+                    .map(|(cell, &w)| match align {
+                        true => format!("{cell:w$}"),
+                        false => cell.clone(),
+                    })
+                    .join(" | ")
+                    .trim_end()
+                    .to_string()
+            })
+            .join("\n")
+    }
+}
+
+fn is_element(node: &Handle, names: &[&str]) -> bool {
+    match &node.data {
+        NodeData::Element { name, .. } => names.contains(&&*name.local),
+        _ => false,
+    }
+}
+
+pub(super) fn attr(node: &Handle, attr: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|a| &*a.name.local == attr)
+            .map(|a| a.value.to_string()),
+        _ => None,
+    }
+}
+
+pub(super) fn raw_text(node: &Handle) -> String {
+    match &node.data {
+        NodeData::Text { contents } => contents.borrow().to_string(),
+        _ => node.children.borrow().iter().map(raw_text).join(""),
+    }
+}
+
+fn block(text: &str) -> String {
+    match text.trim().is_empty() {
+        true => String::new(),
+        false => format!("\n\n{}\n\n", text.trim_matches('\n')),
+    }
+}
+
+/// Trims trailing whitespace from lines and collapses runs of blank lines.
+fn normalise(text: &str) -> String {
+    Regex::new(r"\n{3,}")
+        .unwrap()
+        .replace_all(&text.split('\n').map(str::trim_end).join("\n"), "\n\n")
+        .trim_matches('\n')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_and_entities() {
+        assert_eq!(
+            html_to_text("<p>Fish &amp; chips</p><p>Line<br>break</p>"),
+            "Fish & chips\r\n\r\nLine\r\nbreak"
+        );
+    }
+
+    #[test]
+    fn whitespace_collapsed() {
+        assert_eq!(
+            html_to_text("<div>\n  Some   <b>bold</b>\n  text\n</div>"),
+            "Some bold text"
+        );
+    }
+
+    #[test]
+    fn links_as_footnotes() {
+        assert_eq!(
+            html_to_text(
+                r#"<p><a href="https://a.com/x">This</a> and <a href="https://b.com">b.com</a> and <a href="https://a.com/x">this</a>.</p>"#
+            ),
+            "This[1] and b.com and this[1].\r\n\r\n[1] https://a.com/x"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            html_to_text("<ul><li>one</li><li>two<ol><li>a</li><li>b</li></ol></li></ul>"),
+            "* one\r\n* two\r\n  1. a\r\n  2. b"
+        );
+    }
+
+    #[test]
+    fn blockquotes_nested() {
+        assert_eq!(
+            html_to_text("<p>Yes</p><blockquote>Sure?<blockquote>Well</blockquote></blockquote>"),
+            "Yes\r\n\r\n> Sure?\r\n>\r\n>> Well"
+        );
+    }
+
+    #[test]
+    fn tables_aligned() {
+        assert_eq!(
+            html_to_text(
+                "<table><thead><tr><th>Item</th><th>Qty</th></tr></thead><tbody><tr><td>Apples</td><td>3</td></tr></tbody></table>"
+            ),
+            "Item   | Qty\r\nApples | 3"
+        );
+    }
+
+    #[test]
+    fn pre_preserved_and_scripts_dropped() {
+        assert_eq!(
+            html_to_text("<style>p {}</style><pre>fn main() {\n    42\n}</pre>"),
+            "fn main() {\r\n    42\r\n}"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use html5ever::parse_document;
+use html5ever::tendril::TendrilSink;
+use markup5ever_rcdom::Handle;
+use markup5ever_rcdom::NodeData;
+use markup5ever_rcdom::RcDom;
+use regex::Regex;
+use serde::Serialize;
+use url::Url;
+
+use super::html::attr;
+use super::html::raw_text;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum LinkRisk {
+    /// The link's text is a URL or domain, but not the one it goes to.
+    DomainMismatch { shown: String },
+    /// The domain is internationalised, so may be imitating another.
+    Punycode { unicode: String },
+    /// The domain mixes scripts, or is made of letters that look Latin.
+    Homoglyph,
+    /// The link goes via a redirector or shortener, to `target` if known.
+    Redirect { target: Option<String> },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LinkWarning {
+    pub href: String,
+    pub text: String,
+    /// Where the link really goes, after any redirector.
+    pub domain: String,
+    pub risks: Vec<LinkRisk>,
+}
+
+/// Redirectors' domains and paths, and the query parameter with their target
+/// (if they're not just shorteners, with it hidden).
+const REDIRECTORS: &[(&str, &str, Option<&str>)] = &[
+    ("bit.ly", "/", None),
+    ("goo.gl", "/", None),
+    ("google.com", "/url", Some("q")),
+    ("l.facebook.com", "/l.php", Some("u")),
+    ("lnkd.in", "/", None),
+    ("ow.ly", "/", None),
+    ("safelinks.protection.outlook.com", "/", Some("url")),
+    ("t.co", "/", None),
+    ("tinyurl.com", "/", None),
+    ("urldefense.proofpoint.com", "/v2/url", Some("u")),
+    ("youtube.com", "/redirect", Some("q")),
+];
+
+/// Cyrillic and Greek letters that look like Latin ones.
+const CONFUSABLES: &str = "аеорсхуіјѕԁһӏԛԝвкмнтαεικνορτυχ";
+
+fn is_subdomain(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+fn host(url: &Url) -> Option<String> {
+    Some(url.host_str()?.trim_end_matches('.').to_lowercase())
+}
+
+/// The redirector `url` is, if it is one, with its target if known.
+fn redirect(url: &Url) -> Option<Option<String>> {
+    let host = host(url)?;
+    let (_, _, param) = REDIRECTORS
+        .iter()
+        .find(|(domain, path, _)| is_subdomain(&host, domain) && url.path().starts_with(path))?;
+
+    Some(param.and_then(|p| {
+        let target = url.query_pairs().find(|(k, _)| k == p)?.1.to_string();
+        match host.as_str() {
+            // Which escapes as `-XX` rather than `%XX`, and `/` as `_`.
+            "urldefense.proofpoint.com" => url::form_urlencoded::parse(
+                format!("u={}", target.replace('-', "%").replace('_', "/")).as_bytes(),
+            )
+            .next()
+            .map(|(_, v)| v.to_string()),
+            _ => Some(target),
+        }
+    }))
+}
+
+/// The domain that makes up `text`, if it's just a URL or domain.
+fn shown_domain(text: &str) -> Option<String> {
+    let domain = Regex::new(
+        r"(?i)^(?:[a-z][a-z0-9+.-]*://)?([\p{L}\p{N}-]+(?:\.[\p{L}\p{N}-]+)*\.(?:\p{L}{2,}|xn--[a-z0-9-]+))\.?(?::\d+)?(?:[/?#]\S*)?$",
+    )
+    .unwrap()
+    .captures(text.trim())?[1]
+        .to_lowercase();
+    Some(url::quirks::domain_to_ascii(&domain)).filter(|d| !d.is_empty())
+}
+
+/// The domain with just one more label than its public suffix, roughly: what
+/// someone registered, under which anything is theirs.
+pub(crate) fn registered_domain(host: &str) -> String {
+    let labels = host.split('.').collect::<Vec<_>>();
+    let n = match labels.as_slice() {
+        [.., sld, tld]
+            if tld.len() == 2
+                && matches!(*sld, "ac" | "co" | "com" | "edu" | "gov" | "net" | "org") =>
+        {
+            3
+        }
+        _ => 2,
+    };
+    labels[labels.len().saturating_sub(n)..].join(".")
+}
+
+fn is_homoglyph(unicode_domain: &str) -> bool {
+    unicode_domain.split('.').any(|label| {
+        let latin = label.chars().any(|c| c.is_ascii_alphabetic());
+        let lookalike = label
+            .chars()
+            .filter(|c| c.is_alphabetic() && !c.is_ascii())
+            .collect::<Vec<_>>();
+        let other_script = lookalike
+            .iter()
+            .any(|c| matches!(c, '\u{370}'..='\u{3FF}' | '\u{400}'..='\u{52F}'));
+        (latin && other_script)
+            || (!latin
+                && !lookalike.is_empty()
+                && lookalike.iter().all(|c| CONFUSABLES.contains(*c)))
+    })
+}
+
+fn analyse(href: &str, text: &str) -> Option<LinkWarning> {
+    let url = Url::parse(href).ok()?;
+    let mut domain = host(&url)?;
+    let mut risks = vec![];
+
+    if let Some(target) = redirect(&url) {
+        if let Some(h) = target.as_deref().and_then(|t| host(&Url::parse(t).ok()?)) {
+            domain = h;
+        }
+        risks.push(LinkRisk::Redirect { target });
+    }
+
+    let unicode = url::quirks::domain_to_unicode(&domain);
+    if domain.split('.').any(|l| l.starts_with("xn--")) {
+        risks.push(LinkRisk::Punycode {
+            unicode: unicode.clone(),
+        });
+    }
+    if is_homoglyph(&unicode) {
+        risks.push(LinkRisk::Homoglyph);
+    }
+    if let Some(shown) =
+        shown_domain(text).filter(|s| registered_domain(s) != registered_domain(&domain))
+    {
+        risks.push(LinkRisk::DomainMismatch {
+            shown: url::quirks::domain_to_unicode(&shown),
+        });
+    }
+
+    (!risks.is_empty()).then(|| LinkWarning {
+        href: href.into(),
+        text: text.trim().into(),
+        domain: unicode,
+        risks,
+    })
+}
+
+fn anchor_warnings(node: &Handle, warnings: &mut Vec<LinkWarning>) {
+    match &node.data {
+        NodeData::Element { name, .. } if &*name.local == "a" => {
+            if let Some(warning) = attr(node, "href").and_then(|h| analyse(&h, &raw_text(node))) {
+                warnings.push(warning);
+            }
+        }
+        _ => node
+            .children
+            .borrow()
+            .iter()
+            .for_each(|c| anchor_warnings(c, warnings)),
+    }
+}
+
+/// Warnings about the links in (cleaned) HTML that may be phishing.
+pub(crate) fn link_warnings(html: &str) -> Vec<LinkWarning> {
+    let dom = parse_document(RcDom::default(), Default::default()).one(html);
+    let mut warnings = vec![];
+    anchor_warnings(&dom.document, &mut warnings);
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn risks(href: &str, text: &str) -> Vec<LinkRisk> {
+        analyse(href, text).map(|w| w.risks).unwrap_or_default()
+    }
+
+    #[test]
+    fn domain_mismatch() {
+        assert_eq!(
+            risks(
+                "https://evil.example/login",
+                "https://www.paypal.com/signin"
+            ),
+            vec![LinkRisk::DomainMismatch {
+                shown: "www.paypal.com".into()
+            }]
+        );
+        assert_eq!(risks("https://www.paypal.com/x", "paypal.com"), vec![]);
+        assert_eq!(risks("https://mail.bbc.co.uk/x", "www.bbc.co.uk"), vec![]);
+        assert_eq!(risks("https://evil.example/login", "Sign in"), vec![]);
+    }
+
+    #[test]
+    fn punycode_homoglyphs() {
+        assert_eq!(
+            risks("https://xn--80ak6aa92e.com/", "Apple"),
+            vec![
+                LinkRisk::Punycode {
+                    unicode: "аррӏе.com".into()
+                },
+                LinkRisk::Homoglyph,
+            ]
+        );
+        assert_eq!(
+            risks("https://раypal.com/", "Log in"),
+            vec![
+                LinkRisk::Punycode {
+                    unicode: "раypal.com".into()
+                },
+                LinkRisk::Homoglyph,
+            ]
+        );
+        assert_eq!(
+            risks("https://xn--mnchen-3ya.de/", "Visit"),
+            vec![LinkRisk::Punycode {
+                unicode: "münchen.de".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn redirects_followed() {
+        assert_eq!(
+            risks(
+                "https://www.google.com/url?q=https://evil.example/&sa=D",
+                "https://bank.com"
+            ),
+            vec![
+                LinkRisk::Redirect {
+                    target: Some("https://evil.example/".into())
+                },
+                LinkRisk::DomainMismatch {
+                    shown: "bank.com".into()
+                },
+            ]
+        );
+        assert_eq!(
+            analyse(
+                "https://urldefense.proofpoint.com/v2/url?u=https-3A__www.bank.com_login&d=x",
+                "www.bank.com"
+            )
+            .unwrap()
+            .domain,
+            "www.bank.com"
+        );
+        assert_eq!(
+            risks("https://bit.ly/abc", "bit.ly/abc"),
+            vec![LinkRisk::Redirect { target: None }]
+        );
+    }
+
+    #[test]
+    fn links_extracted() {
+        let warnings = link_warnings(
+            r#"<p><a href="https://evil.example/?a=1&amp;b=2" title="x"><b>bank.com</b></a> <a href="https://bank.com">bank.com</a> <a title="x>y" href="https://evil.example">paypal.com</a></p>"#,
+        );
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].href, "https://evil.example/?a=1&b=2");
+        assert_eq!(warnings[0].text, "bank.com");
+        assert_eq!(warnings[1].text, "paypal.com");
+    }
+}
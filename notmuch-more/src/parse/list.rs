@@ -0,0 +1,98 @@
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The mailing list a message came from, per its `List-*` headers (RFC 2369,
+/// RFC 2919, RFC 8058).
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct MailingList {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    /// Empty if posting isn't allowed (`List-Post: NO`).
+    pub post: Vec<String>,
+    pub archive: Vec<String>,
+    pub unsubscribe: Vec<String>,
+    /// Whether an HTTPS `unsubscribe` URL takes a one-click POST.
+    pub unsubscribe_one_click: bool,
+}
+
+/// The `<URL>`s of a `List-*` header, ignoring comments.
+fn urls(value: &str) -> Vec<String> {
+    Regex::new(r"<([^>]*)>")
+        .unwrap()
+        .captures_iter(&Regex::new(r"\([^)]*\)").unwrap().replace_all(value, ""))
+        .map(|c| c[1].split_whitespace().collect::<String>())
+        .filter(|u| !u.is_empty())
+        .collect()
+}
+
+impl MailingList {
+    /// From `(name, value)` headers, if any are `List-*` ones.
+    pub(crate) fn from_headers(headers: &[(String, String)]) -> Option<Self> {
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        };
+        let mut list = Self::default();
+
+        if let Some(id) = header("List-Id") {
+            match id.rsplit_once('<') {
+                Some((name, id)) => {
+                    let name = name.trim().trim_matches('"').trim();
+                    list.name = (!name.is_empty()).then(|| name.into());
+                    list.id = Some(id.trim_end().trim_end_matches('>').into());
+                }
+                None => list.id = Some(id.trim().into()),
+            }
+        }
+        list.post = header("List-Post").map(urls).unwrap_or_default();
+        list.archive = header("List-Archive").map(urls).unwrap_or_default();
+        list.unsubscribe = header("List-Unsubscribe").map(urls).unwrap_or_default();
+        list.unsubscribe_one_click = header("List-Unsubscribe-Post")
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("List-Unsubscribe=One-Click"))
+            && list.unsubscribe.iter().any(|u| u.starts_with("https:"));
+
+        (list != Self::default()).then_some(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_headers_parsed() {
+        let headers = [
+            ("List-Id", "\"Rust Users\" <users.rust-lang.org>"),
+            ("List-Post", "NO (posting not allowed)"),
+            ("List-Archive", "<https://users.rust-lang.org/archive>"),
+            (
+                "List-Unsubscribe",
+                "<mailto:unsub@rust-lang.org?subject=unsubscribe>,\r\n <https://rust-lang.org/unsub\r\n /abc> (web)",
+            ),
+            ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        assert_eq!(
+            MailingList::from_headers(&headers),
+            Some(MailingList {
+                id: Some("users.rust-lang.org".into()),
+                name: Some("Rust Users".into()),
+                post: vec![],
+                archive: vec!["https://users.rust-lang.org/archive".into()],
+                unsubscribe: vec![
+                    "mailto:unsub@rust-lang.org?subject=unsubscribe".into(),
+                    "https://rust-lang.org/unsub/abc".into(),
+                ],
+                unsubscribe_one_click: true,
+            })
+        );
+        assert_eq!(
+            MailingList::from_headers(&[("Subject".into(), "Hi".into())]),
+            None
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use chrono::DateTime;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// One server's handling of a message, from its `Received` header.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Hop {
+    pub from: Option<String>,
+    pub by: Option<String>,
+    pub with: Option<String>,
+    pub id: Option<String>,
+    #[serde(rename = "for")]
+    pub for_: Option<String>,
+    pub timestamp: Option<i64>,
+    /// Seconds since the previous hop, if both have a time; negative if
+    /// their clocks disagree.
+    pub delay: Option<i64>,
+}
+
+/// Drops (nested) comments, which servers fill with whatever they like.
+pub(crate) fn strip_comments(value: &str) -> String {
+    let mut depth = 0;
+    value
+        .chars()
+        .filter(|&c| {
+            match c {
+                '(' => depth += 1,
+                ')' if depth > 0 => {
+                    depth -= 1;
+                    return false;
+                }
+                _ => (),
+            }
+            depth == 0
+        })
+        .collect()
+}
+
+fn parse_hop(value: &str) -> Hop {
+    let (clauses, date) = match value.rsplit_once(';') {
+        Some((clauses, date)) => (clauses, Some(date)),
+        None => (value, None),
+    };
+    let mut hop = Hop {
+        timestamp: date
+            .and_then(|d| DateTime::parse_from_rfc2822(strip_comments(d).trim()).ok())
+            .map(|d| d.timestamp()),
+        ..Default::default()
+    };
+
+    let clauses = strip_comments(clauses);
+    let mut tokens = clauses.split_whitespace();
+    while let Some(keyword) = tokens.next() {
+        let field = match keyword.to_lowercase().as_str() {
+            "from" => &mut hop.from,
+            "by" => &mut hop.by,
+            "with" => &mut hop.with,
+            "id" => &mut hop.id,
+            "for" => &mut hop.for_,
+            _ => continue,
+        };
+        // With any `;` of the date after it, as in `delivery_hop`'s headers.
+        if field.is_none() {
+            *field = tokens.next().map(|t| {
+                t.trim_start_matches('<')
+                    .trim_end_matches([';', '>'])
+                    .to_string()
+            });
+        }
+    }
+    hop
+}
+
+/// The hops of a message, from `(name, value)` headers, in the order they
+/// happened: the first server it was sent to, through to its delivery.
+pub(crate) fn parse_received(headers: &[(String, String)]) -> Vec<Hop> {
+    let mut hops = headers
+        .iter()
+        .rev()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Received"))
+        .map(|(_, value)| parse_hop(value))
+        .collect::<Vec<_>>();
+
+    for i in 1..hops.len() {
+        hops[i].delay = hops[i]
+            .timestamp
+            .zip(hops[i - 1].timestamp)
+            .map(|(t, prev)| t - prev);
+    }
+    hops
+}
+
+/// The hop at which a message was delivered, from `received`: its Received
+/// headers run together, most recent first, as notmuch gives them. Its `for`
+/// is the most recent one given, as the last server may not say.
+pub(crate) fn delivery_hop(received: &str) -> Hop {
+    let received = strip_comments(received);
+    let date = received.split_once(';').map_or("", |(_, rest)| rest);
+    let tokens = date.split_whitespace().collect::<Vec<_>>();
+    Hop {
+        for_: parse_hop(&received).for_,
+        // A date is five tokens, or six with the day of the week, after which
+        // the next header runs on.
+        timestamp: [6, 5]
+            .into_iter()
+            .find_map(|n| DateTime::parse_from_rfc2822(&tokens.get(..n)?.join(" ")).ok())
+            .map(|d| d.timestamp()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hops_parsed_in_order() {
+        let headers = [
+            (
+                "Received",
+                "by 2002:a05:6a10:1234 with SMTP id abc123;\r\n        Tue, 2 Jan 2024 10:00:30 -0800 (PST)",
+            ),
+            ("Subject", "Hi"),
+            (
+                "Received",
+                "from mail.example.com (mail.example.com [192.0.2.1] (may be forged))\r\n by mx.google.com with ESMTPS id xyz.1\r\n for <me@gmail.com>\r\n (version=TLS1_3 cipher=TLS_AES_256_GCM_SHA384);\r\n Tue, 02 Jan 2024 18:00:00 +0000",
+            ),
+            ("Received", "from localhost by mail.example.com; garbage"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+
+        let hops = parse_received(&headers);
+
+        assert_eq!(hops.len(), 3);
+        assert_eq!(hops[0].from.as_deref(), Some("localhost"));
+        assert_eq!(hops[0].timestamp, None);
+        assert_eq!(hops[1].delay, None);
+        assert_eq!(
+            hops[1],
+            Hop {
+                from: Some("mail.example.com".into()),
+                by: Some("mx.google.com".into()),
+                with: Some("ESMTPS".into()),
+                id: Some("xyz.1".into()),
+                for_: Some("me@gmail.com".into()),
+                timestamp: Some(1704218400),
+                delay: None,
+            }
+        );
+        assert_eq!(hops[2].by.as_deref(), Some("2002:a05:6a10:1234"));
+        assert_eq!(hops[2].from, None);
+        assert_eq!(hops[2].delay, Some(30));
+    }
+
+    #[test]
+    fn delivery_hop_of_notmuchs_headers() {
+        let hop = delivery_hop(
+            "by 2002:a05:6a10:1234 with SMTP id abc123; 2 Jan 2024 10:00:30 -0800 (PST) \
+            from mail.example.com (a; b) by mx.google.com for <me@gmail.com>; Tue, 02 Jan 2024 18:00:00 +0000 \
+            from localhost by mail.example.com; garbage",
+        );
+
+        assert_eq!(hop.for_.as_deref(), Some("me@gmail.com"));
+        assert_eq!(hop.timestamp, Some(1704218430));
+        assert_eq!(
+            delivery_hop("by a; Tue, 02 Jan 2024 18:00:00 +0000").timestamp,
+            Some(1704218400)
+        );
+        assert_eq!(delivery_hop("by a; garbage"), Hop::default());
+    }
+}
@@ -0,0 +1,314 @@
+use html5ever::Attribute;
+use html5ever::QualName;
+use html5ever::local_name;
+use html5ever::namespace_url;
+use html5ever::ns;
+use html5ever::parse_fragment;
+use html5ever::serialize;
+use html5ever::tendril::TendrilSink;
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use markup5ever_rcdom::Handle;
+use markup5ever_rcdom::NodeData;
+use markup5ever_rcdom::RcDom;
+use markup5ever_rcdom::SerializableHandle;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::html::attr;
+
+/// Whether to load remote content, such as images, in HTML bodies.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteContent {
+    #[default]
+    Block,
+    Allow,
+}
+
+/// CSS properties kept in `style` attributes: those that only affect how the
+/// content looks, not where it's positioned nor anything it loads.
+const STYLE_PROPERTIES: &[&str] = &[
+    "background-color",
+    "border",
+    "border-bottom",
+    "border-collapse",
+    "border-color",
+    "border-left",
+    "border-radius",
+    "border-right",
+    "border-spacing",
+    "border-style",
+    "border-top",
+    "border-width",
+    "color",
+    "direction",
+    "display",
+    "font",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "height",
+    "letter-spacing",
+    "line-height",
+    "list-style-type",
+    "margin",
+    "margin-bottom",
+    "margin-left",
+    "margin-right",
+    "margin-top",
+    "max-width",
+    "min-width",
+    "padding",
+    "padding-bottom",
+    "padding-left",
+    "padding-right",
+    "padding-top",
+    "text-align",
+    "text-decoration",
+    "text-indent",
+    "text-transform",
+    "vertical-align",
+    "visibility",
+    "white-space",
+    "width",
+    "word-break",
+];
+
+/// Attributes whose values are URLs, in which `data:` is only allowed for images.
+const URL_ATTRIBUTES: &[&str] = &["action", "cite", "formaction", "href", "longdesc", "src"];
+
+pub(crate) struct CleanHtml {
+    pub html: String,
+    pub remote_blocked: usize,
+    pub trackers_removed: usize,
+}
+
+/// The allowed declarations of a `style` attribute, without any that could
+/// load something or escape (or comment) their way past this.
+fn sanitise_style(style: &str) -> String {
+    style
+        .split(';')
+        .filter_map(|d| {
+            let (property, value) = d.split_once(':')?;
+            let property = property.trim().to_lowercase();
+            let value = value.trim();
+            let lower = value.to_lowercase();
+            let allowed = STYLE_PROPERTIES.contains(&property.as_str())
+                && !["\\", "/*", "@", "javascript:"]
+                    .iter()
+                    .any(|s| lower.contains(s))
+                && Regex::new(r"([a-z-]*)\(")
+                    .unwrap()
+                    .captures_iter(&lower)
+                    .all(|c| matches!(&c[1], "rgb" | "rgba" | "hsl" | "hsla"));
+            allowed.then(|| format!("{property}: {value}"))
+        })
+        .join("; ")
+}
+
+fn is_remote(src: &str) -> bool {
+    let src = src.trim_start().to_lowercase();
+    src.starts_with("http:") || src.starts_with("https:") || src.starts_with("//")
+}
+
+/// Whether an image is only there to tell the sender it's been loaded: too
+/// small or hidden to be seen, or from a known tracking URL.
+fn is_tracker(img: &Handle, src: &str) -> bool {
+    let tiny = ["width", "height"]
+        .iter()
+        .any(|a| attr(img, a).is_some_and(|v| matches!(v.trim(), "0" | "1" | "0px" | "1px")));
+    let hidden = attr(img, "style").is_some_and(|s| {
+        Regex::new(
+            r"(?:^|;)\s*(?:(?:width|height):\s*[01](?:px)?|display:\s*none|visibility:\s*hidden)\s*(?:;|$)",
+        )
+        .unwrap()
+        .is_match(&s.to_lowercase())
+    });
+    let tracking_url = Regex::new(
+        r"(?i)/track/open|/wf/open|/open\.(?:aspx|gif|php)|/trk\?|/beacon|/pixel(?:\.gif|\.png|/|\?)|emltrk|mailtrack|list-manage\.com/track",
+    )
+    .unwrap()
+    .is_match(src);
+
+    is_remote(src) && (tiny || hidden || tracking_url)
+}
+
+fn is_element(node: &Handle, name: &str) -> bool {
+    matches!(&node.data, NodeData::Element { name: n, .. } if &*n.local == name)
+}
+
+/// The id of the part a `cid:` URL refers to, by its `cids`.
+fn cid_part<'a>(src: &str, cids: &'a HashMap<String, String>) -> Option<&'a String> {
+    let (scheme, cid) = src.trim().split_once(':')?;
+    if !scheme.eq_ignore_ascii_case("cid") {
+        return None;
+    }
+    cids.get(&*percent_encoding::percent_decode_str(cid).decode_utf8_lossy())
+}
+
+/// Removes tracking pixels, and remote images' sources unless `remote` allows,
+/// from within `node`, counting them in `clean`. `cid:` images' sources become
+/// the `data-part-id` of the part, in `cids`, to be fetched by. Links are
+/// titled with where they go.
+fn filter_elements(
+    node: &Handle,
+    remote: RemoteContent,
+    cids: &HashMap<String, String>,
+    clean: &mut CleanHtml,
+) {
+    node.children.borrow_mut().retain(|child| {
+        if !is_element(child, "img") {
+            return true;
+        }
+        let src = attr(child, "src").unwrap_or_default();
+        if is_tracker(child, &src) {
+            clean.trackers_removed += 1;
+            return false;
+        }
+        let NodeData::Element { attrs, .. } = &child.data else {
+            return true;
+        };
+        if is_remote(&src) && remote == RemoteContent::Block {
+            clean.remote_blocked += 1;
+            attrs
+                .borrow_mut()
+                .retain(|a| a.name.local != local_name!("src"));
+        }
+        if let Some(part_id) = cid_part(&src, cids) {
+            let mut attrs = attrs.borrow_mut();
+            attrs.retain(|a| a.name.local != local_name!("src"));
+            attrs.push(Attribute {
+                name: QualName::new(None, ns!(), "data-part-id".into()),
+                value: part_id.as_str().into(),
+            });
+        }
+        true
+    });
+
+    for child in node.children.borrow().iter() {
+        if is_element(child, "a")
+            && let (Some(href), NodeData::Element { attrs, .. }) =
+                (attr(child, "href"), &child.data)
+        {
+            let mut attrs = attrs.borrow_mut();
+            attrs.retain(|a| a.name.local != local_name!("title"));
+            attrs.push(Attribute {
+                name: QualName::new(None, ns!(), local_name!("title")),
+                value: href.into(),
+            });
+        }
+        filter_elements(child, remote, cids, clean);
+    }
+}
+
+/// Sanitises HTML for display: only harmless inline styling is kept (`<style>`
+/// sheets are dropped), `data:` and `cid:` images are allowed, remote ones
+/// only if `remote` allows, and tracking pixels never are.
+pub(crate) fn clean_html(
+    html: &str,
+    remote: RemoteContent,
+    cids: &HashMap<String, String>,
+) -> CleanHtml {
+    let cleaned = ammonia::Builder::default()
+        .add_generic_attributes(&["style"])
+        .add_url_schemes(&["cid", "data"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            (_, "style") => Some(sanitise_style(value).into()),
+            ("img", "src") => Some(value.into()),
+            (_, a)
+                if URL_ATTRIBUTES.contains(&a)
+                    && value.trim_start().to_lowercase().starts_with("data:") =>
+            {
+                None
+            }
+            _ => Some(value.into()),
+        })
+        .set_tag_attribute_value("a", "target", "_blank")
+        .clean(html)
+        .to_string();
+
+    // Judged on the parsed HTML, as it'll be displayed, rather than on its
+    // serialisation, in which attribute values can look like tags.
+    let dom = parse_fragment(
+        RcDom::default(),
+        Default::default(),
+        QualName::new(None, ns!(html), local_name!("body")),
+        vec![],
+    )
+    .one(cleaned);
+    let root = dom.document.children.borrow()[0].clone();
+    let mut clean = CleanHtml {
+        html: String::new(),
+        remote_blocked: 0,
+        trackers_removed: 0,
+    };
+    filter_elements(&root, remote, cids, &mut clean);
+
+    let mut html = vec![];
+    serialize(
+        &mut html,
+        &SerializableHandle::from(root),
+        Default::default(),
+    )
+    .expect("Serialising to memory can't fail");
+    clean.html = String::from_utf8_lossy(&html).into();
+    clean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HTML: &str = r#"<p style="color: red; position: fixed; background: url(https://x.com/a.png)">Hi</p>
+<img src="https://example.com/logo.png" alt="Logo">
+<img src="cid:logo@example.com"><img src="data:image/png;base64,AAAA">
+<img src="https://t.example.com/o.gif" width="1" height="1">
+<img src="https://example.com/track/open?u=1">
+<a href="data:text/html,<script>alert(1)</script>">x</a>
+<img alt="a>b" src="https://t.example.com/p.gif" width="1"><img alt="c>d" src="https://example.com/e.png">"#;
+
+    #[test]
+    fn remote_content_blocked() {
+        let clean = clean_html(HTML, RemoteContent::Block, &HashMap::new());
+
+        assert_eq!(
+            clean.html,
+            r#"<p style="color: red">Hi</p>
+<img alt="Logo">
+<img src="cid:logo@example.com"><img src="data:image/png;base64,AAAA">
+
+
+<a rel="noopener noreferrer" target="_blank">x</a>
+<img alt="c>d">"#
+        );
+        assert_eq!(clean.remote_blocked, 2);
+        assert_eq!(clean.trackers_removed, 3);
+    }
+
+    #[test]
+    fn remote_content_allowed_but_not_trackers() {
+        let clean = clean_html(HTML, RemoteContent::Allow, &HashMap::new());
+
+        assert!(
+            clean
+                .html
+                .contains(r#"<img src="https://example.com/logo.png" alt="Logo">"#)
+        );
+        assert_eq!(clean.remote_blocked, 0);
+        assert_eq!(clean.trackers_removed, 3);
+    }
+
+    #[test]
+    fn styles_sanitised() {
+        assert_eq!(
+            sanitise_style(
+                "COLOR: Blue;font-weight:bold; width: expression(alert(1)); margin: 0 /**/; border-color: rgb(1, 2, 3)"
+            ),
+            "color: Blue; font-weight: bold; border-color: rgb(1, 2, 3)"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Duration;
+use chrono::FixedOffset;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Weekday;
+use chrono_tz::Tz;
+
+/// Windows time zone names, as Outlook and Exchange give for TZIDs, and their
+/// IANA equivalents (CLDR's windowsZones, for territory 001).
+const WINDOWS_ZONES: &[(&str, &str)] = &[
+    ("Dateline Standard Time", "Etc/GMT+12"),
+    ("UTC-11", "Etc/GMT+11"),
+    ("Aleutian Standard Time", "America/Adak"),
+    ("Hawaiian Standard Time", "Pacific/Honolulu"),
+    ("Marquesas Standard Time", "Pacific/Marquesas"),
+    ("Alaskan Standard Time", "America/Anchorage"),
+    ("UTC-09", "Etc/GMT+9"),
+    ("Pacific Standard Time (Mexico)", "America/Tijuana"),
+    ("UTC-08", "Etc/GMT+8"),
+    ("Pacific Standard Time", "America/Los_Angeles"),
+    ("US Mountain Standard Time", "America/Phoenix"),
+    ("Mountain Standard Time (Mexico)", "America/Mazatlan"),
+    ("Mountain Standard Time", "America/Denver"),
+    ("Yukon Standard Time", "America/Whitehorse"),
+    ("Central America Standard Time", "America/Guatemala"),
+    ("Central Standard Time", "America/Chicago"),
+    ("Easter Island Standard Time", "Pacific/Easter"),
+    ("Central Standard Time (Mexico)", "America/Mexico_City"),
+    ("Canada Central Standard Time", "America/Regina"),
+    ("SA Pacific Standard Time", "America/Bogota"),
+    ("Eastern Standard Time (Mexico)", "America/Cancun"),
+    ("Eastern Standard Time", "America/New_York"),
+    ("Haiti Standard Time", "America/Port-au-Prince"),
+    ("Cuba Standard Time", "America/Havana"),
+    ("US Eastern Standard Time", "America/Indiana/Indianapolis"),
+    ("Turks And Caicos Standard Time", "America/Grand_Turk"),
+    ("Paraguay Standard Time", "America/Asuncion"),
+    ("Atlantic Standard Time", "America/Halifax"),
+    ("Venezuela Standard Time", "America/Caracas"),
+    ("Central Brazilian Standard Time", "America/Cuiaba"),
+    ("SA Western Standard Time", "America/La_Paz"),
+    ("Pacific SA Standard Time", "America/Santiago"),
+    ("Newfoundland Standard Time", "America/St_Johns"),
+    ("Tocantins Standard Time", "America/Araguaina"),
+    ("E. South America Standard Time", "America/Sao_Paulo"),
+    ("SA Eastern Standard Time", "America/Cayenne"),
+    ("Argentina Standard Time", "America/Argentina/Buenos_Aires"),
+    ("Greenland Standard Time", "America/Nuuk"),
+    ("Montevideo Standard Time", "America/Montevideo"),
+    ("Magallanes Standard Time", "America/Punta_Arenas"),
+    ("Saint Pierre Standard Time", "America/Miquelon"),
+    ("Bahia Standard Time", "America/Bahia"),
+    ("UTC-02", "Etc/GMT+2"),
+    ("Azores Standard Time", "Atlantic/Azores"),
+    ("Cape Verde Standard Time", "Atlantic/Cape_Verde"),
+    ("UTC", "Etc/UTC"),
+    ("GMT Standard Time", "Europe/London"),
+    ("Greenwich Standard Time", "Atlantic/Reykjavik"),
+    ("Sao Tome Standard Time", "Africa/Sao_Tome"),
+    ("Morocco Standard Time", "Africa/Casablanca"),
+    ("W. Europe Standard Time", "Europe/Berlin"),
+    ("Central Europe Standard Time", "Europe/Budapest"),
+    ("Romance Standard Time", "Europe/Paris"),
+    ("Central European Standard Time", "Europe/Warsaw"),
+    ("W. Central Africa Standard Time", "Africa/Lagos"),
+    ("Jordan Standard Time", "Asia/Amman"),
+    ("GTB Standard Time", "Europe/Bucharest"),
+    ("Middle East Standard Time", "Asia/Beirut"),
+    ("Egypt Standard Time", "Africa/Cairo"),
+    ("E. Europe Standard Time", "Europe/Chisinau"),
+    ("Syria Standard Time", "Asia/Damascus"),
+    ("West Bank Standard Time", "Asia/Hebron"),
+    ("South Africa Standard Time", "Africa/Johannesburg"),
+    ("FLE Standard Time", "Europe/Kyiv"),
+    ("Israel Standard Time", "Asia/Jerusalem"),
+    ("South Sudan Standard Time", "Africa/Juba"),
+    ("Kaliningrad Standard Time", "Europe/Kaliningrad"),
+    ("Sudan Standard Time", "Africa/Khartoum"),
+    ("Libya Standard Time", "Africa/Tripoli"),
+    ("Namibia Standard Time", "Africa/Windhoek"),
+    ("Arabic Standard Time", "Asia/Baghdad"),
+    ("Turkey Standard Time", "Europe/Istanbul"),
+    ("Arab Standard Time", "Asia/Riyadh"),
+    ("Belarus Standard Time", "Europe/Minsk"),
+    ("Russian Standard Time", "Europe/Moscow"),
+    ("E. Africa Standard Time", "Africa/Nairobi"),
+    ("Volgograd Standard Time", "Europe/Volgograd"),
+    ("Iran Standard Time", "Asia/Tehran"),
+    ("Arabian Standard Time", "Asia/Dubai"),
+    ("Astrakhan Standard Time", "Europe/Astrakhan"),
+    ("Azerbaijan Standard Time", "Asia/Baku"),
+    ("Russia Time Zone 3", "Europe/Samara"),
+    ("Mauritius Standard Time", "Indian/Mauritius"),
+    ("Saratov Standard Time", "Europe/Saratov"),
+    ("Georgian Standard Time", "Asia/Tbilisi"),
+    ("Caucasus Standard Time", "Asia/Yerevan"),
+    ("Afghanistan Standard Time", "Asia/Kabul"),
+    ("West Asia Standard Time", "Asia/Tashkent"),
+    ("Ekaterinburg Standard Time", "Asia/Yekaterinburg"),
+    ("Pakistan Standard Time", "Asia/Karachi"),
+    ("Qyzylorda Standard Time", "Asia/Qyzylorda"),
+    ("India Standard Time", "Asia/Kolkata"),
+    ("Sri Lanka Standard Time", "Asia/Colombo"),
+    ("Nepal Standard Time", "Asia/Kathmandu"),
+    ("Central Asia Standard Time", "Asia/Bishkek"),
+    ("Bangladesh Standard Time", "Asia/Dhaka"),
+    ("Omsk Standard Time", "Asia/Omsk"),
+    ("Myanmar Standard Time", "Asia/Yangon"),
+    ("SE Asia Standard Time", "Asia/Bangkok"),
+    ("Altai Standard Time", "Asia/Barnaul"),
+    ("W. Mongolia Standard Time", "Asia/Hovd"),
+    ("North Asia Standard Time", "Asia/Krasnoyarsk"),
+    ("N. Central Asia Standard Time", "Asia/Novosibirsk"),
+    ("Tomsk Standard Time", "Asia/Tomsk"),
+    ("China Standard Time", "Asia/Shanghai"),
+    ("North Asia East Standard Time", "Asia/Irkutsk"),
+    ("Singapore Standard Time", "Asia/Singapore"),
+    ("W. Australia Standard Time", "Australia/Perth"),
+    ("Taipei Standard Time", "Asia/Taipei"),
+    ("Ulaanbaatar Standard Time", "Asia/Ulaanbaatar"),
+    ("Aus Central W. Standard Time", "Australia/Eucla"),
+    ("Transbaikal Standard Time", "Asia/Chita"),
+    ("Tokyo Standard Time", "Asia/Tokyo"),
+    ("North Korea Standard Time", "Asia/Pyongyang"),
+    ("Korea Standard Time", "Asia/Seoul"),
+    ("Yakutsk Standard Time", "Asia/Yakutsk"),
+    ("Cen. Australia Standard Time", "Australia/Adelaide"),
+    ("AUS Central Standard Time", "Australia/Darwin"),
+    ("E. Australia Standard Time", "Australia/Brisbane"),
+    ("AUS Eastern Standard Time", "Australia/Sydney"),
+    ("West Pacific Standard Time", "Pacific/Port_Moresby"),
+    ("Tasmania Standard Time", "Australia/Hobart"),
+    ("Vladivostok Standard Time", "Asia/Vladivostok"),
+    ("Lord Howe Standard Time", "Australia/Lord_Howe"),
+    ("Bougainville Standard Time", "Pacific/Bougainville"),
+    ("Russia Time Zone 10", "Asia/Srednekolymsk"),
+    ("Magadan Standard Time", "Asia/Magadan"),
+    ("Norfolk Standard Time", "Pacific/Norfolk"),
+    ("Sakhalin Standard Time", "Asia/Sakhalin"),
+    ("Central Pacific Standard Time", "Pacific/Guadalcanal"),
+    ("Russia Time Zone 11", "Asia/Kamchatka"),
+    ("New Zealand Standard Time", "Pacific/Auckland"),
+    ("UTC+12", "Etc/GMT-12"),
+    ("Fiji Standard Time", "Pacific/Fiji"),
+    ("Chatham Islands Standard Time", "Pacific/Chatham"),
+    ("UTC+13", "Etc/GMT-13"),
+    ("Tonga Standard Time", "Pacific/Tongatapu"),
+    ("Samoa Standard Time", "Pacific/Apia"),
+    ("Line Islands Standard Time", "Pacific/Kiritimati"),
+];
+
+/// A `STANDARD` or `DAYLIGHT` observance of a `VTIMEZONE`: the offset from
+/// its onset(s).
+#[derive(Clone, Debug, Default)]
+pub(super) struct Observance {
+    pub(super) start: Option<NaiveDateTime>,
+    pub(super) offset: Option<FixedOffset>,
+    pub(super) rrule: Option<String>,
+}
+
+/// The `n`th `weekday` of a month (counting back from its end if negative).
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: i32) -> Option<NaiveDate> {
+    match n {
+        1.. => NaiveDate::from_weekday_of_month_opt(year, month, weekday, n.try_into().ok()?),
+        ..0 => {
+            let last = NaiveDate::from_ymd_opt(year, month, 1)?
+                .checked_add_months(chrono::Months::new(1))?
+                .pred_opt()?;
+            let back =
+                (last.weekday().num_days_from_monday() + 7 - weekday.num_days_from_monday()) % 7;
+            last.checked_sub_signed(Duration::try_days(back as i64 + 7 * (-(n as i64) - 1))?)
+        }
+        0 => None,
+    }
+}
+
+impl Observance {
+    /// When it began in `year`, per its yearly `RRULE` (e.g.
+    /// `FREQ=YEARLY;BYMONTH=10;BYDAY=-1SU`), or else its only onset.
+    fn onset(&self, year: i32) -> Option<NaiveDateTime> {
+        let start = self.start?;
+        let Some(rrule) = &self.rrule else {
+            return Some(start);
+        };
+        let part = |name: &str| {
+            rrule
+                .split(';')
+                .filter_map(|p| p.split_once('='))
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v)
+        };
+        if !part("FREQ").is_some_and(|f| f.eq_ignore_ascii_case("YEARLY")) || year < start.year() {
+            return None;
+        }
+
+        let month = part("BYMONTH")
+            .and_then(|m| m.parse().ok())
+            .unwrap_or(start.month());
+        let date = match part("BYDAY") {
+            Some(byday) => {
+                let (n, day) = byday.split_at_checked(byday.len().checked_sub(2)?)?;
+                let weekday = match day.to_uppercase().as_str() {
+                    "MO" => Weekday::Mon,
+                    "TU" => Weekday::Tue,
+                    "WE" => Weekday::Wed,
+                    "TH" => Weekday::Thu,
+                    "FR" => Weekday::Fri,
+                    "SA" => Weekday::Sat,
+                    "SU" => Weekday::Sun,
+                    _ => return None,
+                };
+                nth_weekday(year, month, weekday, n.parse().unwrap_or(1))?
+            }
+            None => NaiveDate::from_ymd_opt(year, month, start.day())?,
+        };
+        Some(date.and_time(start.time()))
+    }
+}
+
+/// `local` in time zone `tzid`: an IANA or Windows name, or one defined by
+/// the calendar's `VTIMEZONE` of `observances`. `None` if unrecognised.
+pub(super) fn in_zone(
+    tzid: &str,
+    local: &NaiveDateTime,
+    observances: Option<&Vec<Observance>>,
+) -> Option<DateTime<FixedOffset>> {
+    let tz = tzid.parse::<Tz>().ok().or_else(|| {
+        WINDOWS_ZONES
+            .iter()
+            .find(|(windows, _)| windows.eq_ignore_ascii_case(tzid.trim()))
+            .and_then(|(_, iana)| iana.parse().ok())
+    });
+    if let Some(tz) = tz {
+        return Some(tz.from_local_datetime(local).earliest()?.fixed_offset());
+    }
+
+    // The observance in effect is the one that most recently began.
+    let offset = observances?
+        .iter()
+        .flat_map(|o| {
+            [local.year() - 1, local.year()]
+                .into_iter()
+                .filter_map(|y| o.onset(y))
+                .filter(|onset| onset <= local)
+                .map(|onset| (onset, o.offset))
+        })
+        .max_by_key(|(onset, _)| *onset)?
+        .1?;
+    offset.from_local_datetime(local).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_zones_known() {
+        for (windows, iana) in WINDOWS_ZONES {
+            assert!(iana.parse::<Tz>().is_ok(), "{windows}: {iana}");
+        }
+    }
+
+    #[test]
+    fn vtimezone_rules_followed() {
+        let time = |s| NaiveDateTime::parse_from_str(s, "%Y%m%dT%H%M%S").unwrap();
+        let observance = |start, hours, rrule: &str| Observance {
+            start: Some(time(start)),
+            offset: FixedOffset::east_opt(hours * 3600),
+            rrule: Some(rrule.into()),
+        };
+        let observances = vec![
+            observance("16010101T020000", -8, "FREQ=YEARLY;BYDAY=1SU;BYMONTH=11"),
+            observance("16010101T020000", -7, "FREQ=YEARLY;BYDAY=2SU;BYMONTH=3"),
+        ];
+        let in_custom = |s| {
+            in_zone("Custom", &time(s), Some(&observances))
+                .unwrap()
+                .to_rfc3339()
+        };
+
+        assert_eq!(in_custom("20260701T100000"), "2026-07-01T10:00:00-07:00");
+        assert_eq!(in_custom("20260308T010000"), "2026-03-08T01:00:00-08:00");
+        assert_eq!(in_custom("20260308T030000"), "2026-03-08T03:00:00-07:00");
+        assert_eq!(in_custom("20260102T090000"), "2026-01-02T09:00:00-08:00");
+        assert_eq!(
+            in_zone("Pacific Standard Time", &time("20260102T090000"), None)
+                .unwrap()
+                .to_rfc3339(),
+            "2026-01-02T09:00:00-08:00"
+        );
+        assert_eq!(in_zone("Custom", &time("20260102T090000"), None), None);
+    }
+
+    #[test]
+    fn malformed_rules_ignored() {
+        let start = NaiveDateTime::parse_from_str("16010101T020000", "%Y%m%dT%H%M%S").unwrap();
+        for byday in ["éX", "é", "-2147483648SU", "2147483647SU", "300SU"] {
+            let observance = Observance {
+                start: Some(start),
+                offset: FixedOffset::east_opt(0),
+                rrule: Some(format!("FREQ=YEARLY;BYMONTH=3;BYDAY={byday}")),
+            };
+            assert_eq!(observance.onset(2026), None, "{byday}");
+        }
+    }
+}
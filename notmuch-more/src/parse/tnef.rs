@@ -0,0 +1,657 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use encoding_rs::Encoding;
+use encoding_rs::WINDOWS_1252;
+use mailparse::DispositionType;
+
+use super::charset;
+use crate::NotmuchMoreError;
+
+const SIGNATURE: u32 = 0x223E_9F78;
+
+const ATT_BODY: u32 = 0x0002_800C;
+const ATT_ATTACH_TITLE: u32 = 0x0001_8010;
+const ATT_ATTACH_DATA: u32 = 0x0006_800F;
+const ATT_ATTACH_REND_DATA: u32 = 0x0006_9002;
+const ATT_MAPI_PROPS: u32 = 0x0006_9003;
+const ATT_ATTACHMENT: u32 = 0x0006_9005;
+const ATT_OEM_CODEPAGE: u32 = 0x0006_9007;
+
+const PR_BODY: u16 = 0x1000;
+const PR_RTF_COMPRESSED: u16 = 0x1009;
+const PR_HTML: u16 = 0x1013;
+const PR_ATTACH_DATA_BIN: u16 = 0x3701;
+const PR_ATTACH_FILENAME: u16 = 0x3704;
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+const PR_ATTACH_MIME_TAG: u16 = 0x370E;
+
+/// The dictionary that compressed RTF starts with, per MS-OXRTFCP.
+const LZFU_PREBUF: &[u8] = b"{\\rtf1\\ansi\\mac\\deff0\\deftab720{\\fonttbl;}{\\f0\\fnil \\froman \
+    \\fswiss \\fmodern \\fscript \\fdecor MS Sans SerifSymbolArialTimes New RomanCourier\
+    {\\colortbl\\red0\\green0\\blue0\r\n\\par \\pard\\plain\\f0\\fs20\\b\\i\\u\\tab\\tx";
+
+/// A body or attachment decoded from a TNEF (`winmail.dat`) part.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct TnefPart {
+    pub content: Vec<u8>,
+    pub disposition: DispositionType,
+    pub filename: Option<String>,
+    pub mimetype: String,
+}
+
+pub(crate) fn is_tnef(part: &mailparse::ParsedMail) -> bool {
+    matches!(
+        part.ctype.mimetype.as_str(),
+        "application/ms-tnef" | "application/vnd.ms-tnef"
+    ) || part
+        .get_content_disposition()
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .is_some_and(|f| f.eq_ignore_ascii_case("winmail.dat"))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], NotmuchMoreError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(n))
+            .ok_or_else(|| anyhow!("Truncated TNEF at offset {}", self.pos))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    /// Takes `n` bytes, then skips the padding to a multiple of four.
+    fn take_padded(&mut self, n: usize) -> Result<&'a [u8], NotmuchMoreError> {
+        let bytes = self.take(n)?;
+        self.take((4 - n % 4) % 4)?;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, NotmuchMoreError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, NotmuchMoreError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+/// An 8-bit string, in the stream's codepage.
+fn string8(bytes: &[u8], encoding: &'static Encoding) -> String {
+    encoding
+        .decode_without_bom_handling(bytes)
+        .0
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+fn unicode(bytes: &[u8]) -> String {
+    String::from_utf16_lossy(
+        &bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>(),
+    )
+    .trim_end_matches('\0')
+    .to_string()
+}
+
+enum Prop<'a> {
+    Bytes(&'a [u8]),
+    Text(String),
+}
+
+impl Prop<'_> {
+    fn text(&self, encoding: &'static Encoding) -> String {
+        match self {
+            Prop::Bytes(b) => string8(b, encoding),
+            Prop::Text(t) => t.clone(),
+        }
+    }
+}
+
+/// The string and binary MAPI properties in an `attMAPIProps` or
+/// `attAttachment` attribute, by property id.
+fn mapi_props(data: &[u8]) -> Result<HashMap<u16, Prop<'_>>, NotmuchMoreError> {
+    let mut r = Reader { data, pos: 0 };
+    let mut props = HashMap::new();
+
+    for _ in 0..r.u32()? {
+        let ty = r.u16()?;
+        let id = r.u16()?;
+        if id >= 0x8000 {
+            // Named property: GUID, then either a numeric id or a name.
+            r.take(16)?;
+            match r.u32()? {
+                0 => {
+                    r.u32()?;
+                }
+                _ => {
+                    let len = r.u32()? as usize;
+                    r.take_padded(len)?;
+                }
+            }
+        }
+
+        let multi = ty & 0x1000 != 0;
+        match ty & 0x0FFF {
+            // String, unicode, binary and object values are always counted.
+            base @ (0x001E | 0x001F | 0x0102 | 0x000D) => {
+                for i in 0..r.u32()? {
+                    let len = r.u32()? as usize;
+                    let value = r.take_padded(len)?;
+                    if i == 0 {
+                        props.insert(
+                            id,
+                            match base {
+                                0x001F => Prop::Text(unicode(value)),
+                                _ => Prop::Bytes(value),
+                            },
+                        );
+                    }
+                }
+            }
+            base => {
+                let size = match base {
+                    0x0001..=0x0004 | 0x000A | 0x000B => 4,
+                    0x0005..=0x0007 | 0x0014 | 0x0040 => 8,
+                    0x0048 => 16,
+                    _ => return Err(anyhow!("Unknown MAPI property type {:#06x}", ty).into()),
+                };
+                let count = match multi {
+                    true => r.u32()? as usize,
+                    false => 1,
+                };
+                r.take_padded(size * count)?;
+            }
+        }
+    }
+
+    Ok(props)
+}
+
+/// Decompresses `PR_RTF_COMPRESSED`, per MS-OXRTFCP.
+fn decompress_rtf(data: &[u8]) -> Result<Vec<u8>, NotmuchMoreError> {
+    let mut r = Reader { data, pos: 0 };
+    let comp_size = r.u32()? as usize;
+    let raw_size = r.u32()? as usize;
+    let magic = r.u32()?;
+    r.u32()?; // CRC
+
+    let body = r.take(comp_size.saturating_sub(12).min(data.len() - 16))?;
+    match magic {
+        // "MELA": stored uncompressed.
+        0x414C_454D => return Ok(body.to_vec()),
+        // "LZFu"
+        0x7546_5A4C => (),
+        _ => return Err(anyhow!("Unknown compressed RTF type {:#010x}", magic).into()),
+    }
+
+    let mut dict = [0u8; 4096];
+    dict[..LZFU_PREBUF.len()].copy_from_slice(LZFU_PREBUF);
+    let mut write = LZFU_PREBUF.len();
+    let mut out = Vec::with_capacity(raw_size);
+
+    let mut r = Reader { data: body, pos: 0 };
+    while !r.is_empty() {
+        let control = r.take(1)?[0];
+        for bit in 0..8 {
+            if r.is_empty() {
+                break;
+            }
+            match control & (1 << bit) {
+                0 => {
+                    let byte = r.take(1)?[0];
+                    out.push(byte);
+                    dict[write] = byte;
+                    write = (write + 1) % dict.len();
+                }
+                _ => {
+                    let reference = u16::from_be_bytes(r.take(2)?.try_into().unwrap()) as usize;
+                    let offset = reference >> 4;
+                    if offset == write {
+                        return Ok(out);
+                    }
+                    for i in 0..(reference & 0xF) + 2 {
+                        let byte = dict[(offset + i) % dict.len()];
+                        out.push(byte);
+                        dict[write] = byte;
+                        write = (write + 1) % dict.len();
+                    }
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Clone, Default)]
+struct RtfGroup {
+    htmlrtf: bool,
+    htmltag: bool,
+    skip: bool,
+    uc: usize,
+}
+
+enum RtfChar {
+    Char(char),
+    /// In the codepage, of which a character may take several.
+    Byte(u8),
+}
+
+/// The text RTF decodes to, with runs of bytes decoded together.
+struct RtfText {
+    text: String,
+    bytes: Vec<u8>,
+    encoding: &'static Encoding,
+}
+
+impl RtfText {
+    fn flush(&mut self) {
+        if !self.bytes.is_empty() {
+            let (text, _) = self.encoding.decode_without_bom_handling(&self.bytes);
+            self.text.push_str(&text);
+            self.bytes.clear();
+        }
+    }
+
+    /// Adds `c`, unless it's to be skipped.
+    fn emit(&mut self, stack: &[RtfGroup], skip_chars: &mut usize, c: RtfChar) {
+        let group = stack.last().unwrap();
+        if *skip_chars > 0 {
+            *skip_chars -= 1;
+        } else if !group.skip && (group.htmltag || !group.htmlrtf) {
+            match c {
+                RtfChar::Char(c) => {
+                    self.flush();
+                    self.text.push(c);
+                }
+                RtfChar::Byte(b) => self.bytes.push(b),
+            }
+        }
+    }
+}
+
+/// Converts RTF to its text, or if it encapsulates HTML (`\fromhtml1`), to
+/// that HTML; per MS-OXRTFEX. Its bytes are in `encoding` unless it declares
+/// a codepage. Returns whether it was HTML.
+fn decode_rtf(rtf: &[u8], encoding: &'static Encoding) -> (bool, String) {
+    let is_html = rtf.windows(10).any(|w| w == b"\\fromhtml1");
+    let mut out = RtfText {
+        text: String::new(),
+        bytes: vec![],
+        encoding,
+    };
+    let mut stack = vec![RtfGroup {
+        uc: 1,
+        ..Default::default()
+    }];
+    let mut skip_chars = 0;
+    let mut star = false;
+    let emit = |out: &mut RtfText, stack: &[RtfGroup], skip_chars: &mut usize, c: char| {
+        out.emit(stack, skip_chars, RtfChar::Char(c))
+    };
+
+    let mut i = 0;
+    while i < rtf.len() {
+        let c = rtf[i];
+        i += 1;
+        match c {
+            b'{' => {
+                let group = stack.last().cloned().unwrap_or_default();
+                stack.push(group);
+            }
+            b'}' => {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+            }
+            b'\r' | b'\n' => (),
+            b'\\' if i < rtf.len() && rtf[i].is_ascii_alphabetic() => {
+                let start = i;
+                while i < rtf.len() && rtf[i].is_ascii_alphabetic() {
+                    i += 1;
+                }
+                let word = String::from_utf8_lossy(&rtf[start..i]).to_string();
+                let num_start = i;
+                if i < rtf.len() && rtf[i] == b'-' {
+                    i += 1;
+                }
+                while i < rtf.len() && rtf[i].is_ascii_digit() {
+                    i += 1;
+                }
+                let num = std::str::from_utf8(&rtf[num_start..i])
+                    .ok()
+                    .and_then(|n| n.parse::<i32>().ok());
+                if i < rtf.len() && rtf[i] == b' ' {
+                    i += 1;
+                }
+
+                let group = stack.last_mut().unwrap();
+                if std::mem::take(&mut star) && word != "htmltag" {
+                    group.skip = true;
+                }
+                match word.as_str() {
+                    "htmltag" => {
+                        group.htmltag = true;
+                        group.skip = false;
+                    }
+                    "htmlrtf" => group.htmlrtf = num != Some(0),
+                    "uc" => group.uc = num.unwrap_or(1).max(0) as usize,
+                    "ansicpg" => {
+                        out.flush();
+                        out.encoding = charset::for_codepage(num.unwrap_or(0).max(0) as u32);
+                    }
+                    "par" | "line" => "\r\n"
+                        .chars()
+                        .for_each(|c| emit(&mut out, &stack, &mut skip_chars, c)),
+                    "tab" => emit(&mut out, &stack, &mut skip_chars, '\t'),
+                    "u" => {
+                        let n = num.unwrap_or(0);
+                        let code = if n < 0 { n + 65536 } else { n } as u32;
+                        emit(
+                            &mut out,
+                            &stack,
+                            &mut skip_chars,
+                            char::from_u32(code).unwrap_or('\u{FFFD}'),
+                        );
+                        skip_chars = stack.last().unwrap().uc;
+                    }
+                    "fonttbl" | "colortbl" | "stylesheet" | "info" | "pict" | "object"
+                    | "header" | "footer" | "headerl" | "headerr" | "footerl" | "footerr"
+                    | "listtable" | "listoverridetable" | "rsidtbl" | "fldinst" | "themedata"
+                    | "colorschememapping" | "latentstyles" | "datastore" | "xmlnstbl" => {
+                        group.skip = true
+                    }
+                    _ => (),
+                }
+            }
+            b'\\' if i < rtf.len() => {
+                let symbol = rtf[i];
+                i += 1;
+                match symbol {
+                    b'*' => star = true,
+                    b'\'' => {
+                        if let Some(byte) = rtf
+                            .get(i..i + 2)
+                            .and_then(|h| std::str::from_utf8(h).ok())
+                            .and_then(|h| u8::from_str_radix(h, 16).ok())
+                        {
+                            i += 2;
+                            out.emit(&stack, &mut skip_chars, RtfChar::Byte(byte));
+                        }
+                    }
+                    b'{' | b'}' | b'\\' => {
+                        emit(&mut out, &stack, &mut skip_chars, char::from(symbol))
+                    }
+                    b'~' => emit(&mut out, &stack, &mut skip_chars, '\u{A0}'),
+                    b'_' => emit(&mut out, &stack, &mut skip_chars, '-'),
+                    b'\r' | b'\n' => "\r\n"
+                        .chars()
+                        .for_each(|c| emit(&mut out, &stack, &mut skip_chars, c)),
+                    _ => (),
+                }
+            }
+            c => out.emit(&stack, &mut skip_chars, RtfChar::Byte(c)),
+        }
+    }
+
+    out.flush();
+    (is_html, out.text)
+}
+
+/// An attachment's attributes, with its strings decoded once the codepage
+/// is known.
+#[derive(Default)]
+struct Attachment<'a> {
+    content: Vec<u8>,
+    filename: Option<Prop<'a>>,
+    mimetype: Option<Prop<'a>>,
+}
+
+/// Decodes a TNEF stream into its message body, preferring HTML, and the
+/// compressed RTF as `body.rtf` if there is any, followed by its attachments.
+pub(crate) fn parse_tnef(data: &[u8]) -> Result<Vec<TnefPart>, NotmuchMoreError> {
+    let mut r = Reader { data, pos: 0 };
+    if r.u32()? != SIGNATURE {
+        return Err(anyhow!("Not a TNEF stream").into());
+    }
+    r.u16()?; // Legacy key
+
+    let mut encoding = WINDOWS_1252;
+    let mut text = None;
+    let mut html = None;
+    let mut rtf = None;
+    let mut attachments: Vec<Attachment> = vec![];
+
+    while !r.is_empty() {
+        r.take(1)?; // Level: message or attachment, implied by the id
+        let id = r.u32()?;
+        let len = r.u32()? as usize;
+        let value = r.take(len)?;
+        r.u16()?; // Checksum
+
+        match (id, attachments.last_mut()) {
+            (ATT_OEM_CODEPAGE, _) => {
+                if let Some(cp) = value.get(..4) {
+                    encoding = charset::for_codepage(u32::from_le_bytes(cp.try_into().unwrap()));
+                }
+            }
+            (ATT_BODY, _) => text = Some(Prop::Bytes(value)),
+            (ATT_MAPI_PROPS, _) => {
+                let mut props = mapi_props(value)?;
+                if let Some(Prop::Bytes(b)) = props.get(&PR_RTF_COMPRESSED) {
+                    rtf = Some(decompress_rtf(b)?);
+                }
+                html = html.or_else(|| props.remove(&PR_HTML));
+                text = text.or_else(|| props.remove(&PR_BODY));
+            }
+            (ATT_ATTACH_REND_DATA, _) => attachments.push(Attachment::default()),
+            (ATT_ATTACH_TITLE, Some(a)) => a.filename = Some(Prop::Bytes(value)),
+            (ATT_ATTACH_DATA, Some(a)) => a.content = value.to_vec(),
+            (ATT_ATTACHMENT, Some(a)) => {
+                let mut props = mapi_props(value)?;
+                if let Some(f) = props
+                    .remove(&PR_ATTACH_LONG_FILENAME)
+                    .or_else(|| props.remove(&PR_ATTACH_FILENAME))
+                {
+                    a.filename = Some(f);
+                }
+                a.mimetype = props.remove(&PR_ATTACH_MIME_TAG);
+                if let (true, Some(Prop::Bytes(b))) =
+                    (a.content.is_empty(), props.get(&PR_ATTACH_DATA_BIN))
+                {
+                    a.content = b.to_vec();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let text = text.map(|t| t.text(encoding));
+    let mut parts = vec![];
+    let body = match (html, &rtf) {
+        (Some(html), _) => Some(("text/html", html.text(encoding))),
+        (None, Some(rtf)) => match decode_rtf(rtf, encoding) {
+            (true, html) => Some(("text/html", html)),
+            (false, rtf_text) => Some(("text/plain", text.unwrap_or(rtf_text))),
+        },
+        (None, None) => text.map(|t| ("text/plain", t)),
+    };
+    if let Some((mimetype, content)) = body {
+        parts.push(TnefPart {
+            content: content.into_bytes(),
+            disposition: DispositionType::Inline,
+            filename: None,
+            mimetype: mimetype.into(),
+        });
+    }
+    if let Some(rtf) = rtf {
+        parts.push(TnefPart {
+            content: rtf,
+            disposition: DispositionType::Attachment,
+            filename: Some("body.rtf".into()),
+            mimetype: "application/rtf".into(),
+        });
+    }
+    parts.extend(attachments.into_iter().map(|a| {
+        let filename = a.filename.map(|f| f.text(encoding));
+        TnefPart {
+            mimetype: a.mimetype.map(|m| m.text(encoding)).unwrap_or_else(|| {
+                mime_guess::from_path(filename.as_deref().unwrap_or(""))
+                    .first_or_octet_stream()
+                    .to_string()
+            }),
+            content: a.content,
+            disposition: DispositionType::Attachment,
+            filename,
+        }
+    }));
+
+    Ok(parts)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn attribute(level: u8, id: u32, value: &[u8]) -> Vec<u8> {
+        let mut a = vec![level];
+        a.extend(id.to_le_bytes());
+        a.extend((value.len() as u32).to_le_bytes());
+        a.extend(value);
+        let checksum = value.iter().fold(0u16, |s, &b| s.wrapping_add(b as u16));
+        a.extend(checksum.to_le_bytes());
+        a
+    }
+
+    /// A TNEF stream with a plain text body and one attachment.
+    pub(crate) fn sample() -> Vec<u8> {
+        let mut props = 1u32.to_le_bytes().to_vec();
+        props.extend(0x001Fu16.to_le_bytes());
+        props.extend(PR_ATTACH_LONG_FILENAME.to_le_bytes());
+        props.extend(1u32.to_le_bytes());
+        let name = "Quarterly report.txt\0"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        props.extend((name.len() as u32).to_le_bytes());
+        props.extend(&name);
+        props.extend(vec![0; (4 - name.len() % 4) % 4]);
+
+        let mut tnef = SIGNATURE.to_le_bytes().to_vec();
+        tnef.extend(0x0001u16.to_le_bytes());
+        tnef.extend(attribute(1, ATT_BODY, b"See attached.\0"));
+        tnef.extend(attribute(2, ATT_ATTACH_REND_DATA, &[0; 14]));
+        tnef.extend(attribute(2, ATT_ATTACH_TITLE, b"QUARTE~1.TXT\0"));
+        tnef.extend(attribute(2, ATT_ATTACH_DATA, b"Sales up."));
+        tnef.extend(attribute(2, ATT_ATTACHMENT, &props));
+        tnef
+    }
+
+    #[test]
+    fn body_and_attachment() {
+        assert_eq!(
+            parse_tnef(&sample()).unwrap(),
+            vec![
+                TnefPart {
+                    content: b"See attached.".to_vec(),
+                    disposition: DispositionType::Inline,
+                    filename: None,
+                    mimetype: "text/plain".into(),
+                },
+                TnefPart {
+                    content: b"Sales up.".to_vec(),
+                    disposition: DispositionType::Attachment,
+                    filename: Some("Quarterly report.txt".into()),
+                    mimetype: "text/plain".into(),
+                },
+            ]
+        );
+        assert!(parse_tnef(b"not tnef").is_err());
+    }
+
+    #[test]
+    fn strings_in_codepage() {
+        let mut tnef = SIGNATURE.to_le_bytes().to_vec();
+        tnef.extend(0x0001u16.to_le_bytes());
+        tnef.extend(attribute(1, ATT_BODY, b"Price: \x80 5\0"));
+        tnef.extend(attribute(
+            1,
+            ATT_OEM_CODEPAGE,
+            &[0xe4, 0x04, 0, 0, 0, 0, 0, 0],
+        ));
+        tnef.extend(attribute(2, ATT_ATTACH_REND_DATA, &[0; 14]));
+        tnef.extend(attribute(2, ATT_ATTACH_TITLE, b"R\xe9sum\xe9.txt\0"));
+        let parts = parse_tnef(&tnef).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&parts[0].content),
+            "Price: \u{20ac} 5"
+        );
+        assert_eq!(parts[1].filename.as_deref(), Some("R\u{e9}sum\u{e9}.txt"));
+
+        tnef.extend(attribute(1, ATT_OEM_CODEPAGE, &[0xe3, 0x04, 0, 0]));
+        tnef.extend(attribute(2, ATT_ATTACH_TITLE, b"\xc4\xe0.txt\0"));
+        assert_eq!(
+            parse_tnef(&tnef).unwrap()[1].filename.as_deref(),
+            Some("\u{414}\u{430}.txt")
+        );
+    }
+
+    #[test]
+    fn compressed_rtf() {
+        // The example from MS-OXRTFCP.
+        let compressed = [
+            0x2d, 0x00, 0x00, 0x00, 0x2b, 0x00, 0x00, 0x00, 0x4c, 0x5a, 0x46, 0x75, 0xf1, 0xc5,
+            0xc7, 0xa7, 0x03, 0x00, 0x0a, 0x00, 0x72, 0x63, 0x70, 0x67, 0x31, 0x32, 0x35, 0x42,
+            0x32, 0x0a, 0xf3, 0x20, 0x68, 0x65, 0x6c, 0x09, 0x00, 0x20, 0x62, 0x77, 0x05, 0xb0,
+            0x6c, 0x64, 0x7d, 0x0a, 0x80, 0x0f, 0xa0,
+        ];
+
+        assert_eq!(
+            String::from_utf8(decompress_rtf(&compressed).unwrap()).unwrap(),
+            "{\\rtf1\\ansi\\ansicpg1252\\pard hello world}\r\n"
+        );
+    }
+
+    #[test]
+    fn rtf_decoded() {
+        assert_eq!(
+            decode_rtf(
+                b"{\\rtf1\\ansi{\\fonttbl{\\f0 Arial;}}\\pard Caf\\'e9 \\u8364? ok \\'80\\par}",
+                WINDOWS_1252
+            ),
+            (false, "Caf\u{e9} \u{20ac} ok \u{20ac}\r\n".into())
+        );
+        assert_eq!(
+            decode_rtf(
+                b"{\\rtf1\\ansi\\ansicpg932 \\'93\\'fa\\'96\\'7b}",
+                WINDOWS_1252
+            ),
+            (false, "\u{65e5}\u{672c}".into())
+        );
+        assert_eq!(
+            decode_rtf(b"{\\rtf1\\ansi Gr\xfc\xdfe}", charset::for_codepage(1252)),
+            (false, "Gr\u{fc}\u{df}e".into())
+        );
+        assert_eq!(
+            decode_rtf(
+                b"{\\rtf1\\ansi\\fromhtml1{\\*\\htmltag64 <p>}Hi{\\*\\htmltag84 &amp;}\\htmlrtf &\\htmlrtf0 bye{\\*\\htmltag72 </p>}}",
+                WINDOWS_1252
+            ),
+            (true, "<p>Hi&amp;bye</p>".into())
+        );
+    }
+}
@@ -1,29 +1,31 @@
-use std::convert::TryFrom;
-
 use notmuch::Database;
 
+use crate::attachment_text;
 use crate::error::NotmuchMoreError;
 use crate::parse::EmlMeta;
 use crate::parse::EmlParseError;
 
 pub fn count_matches(db: &Database, query: String) -> Result<u32, NotmuchMoreError> {
     println!("Counting matches for query: {query}");
-    let eml_query = db.create_query(&query)?;
+    let eml_query = db.create_query(&attachment_text::rewrite_query(&query))?;
     eml_query.count_messages().map_err(NotmuchMoreError::from)
 }
 
+/// Lists the messages matching `query`, with the authentication results of
+/// the `trusted_authserv_ids` only.
 pub fn list_eml(
     db: &Database,
     query: String,
+    trusted_authserv_ids: &[String],
 ) -> Result<Vec<Result<EmlMeta, EmlParseError>>, NotmuchMoreError> {
     println!("Executing query: {query}");
 
-    let eml_query = db.create_query(&query)?;
+    let eml_query = db.create_query(&attachment_text::rewrite_query(&query))?;
     eml_query.set_sort(notmuch::Sort::NewestFirst);
     let emls = eml_query.search_messages()?;
 
     emls.into_iter()
         .take(25)
-        .map(|m| Ok(EmlMeta::try_from(&m)))
+        .map(|m| Ok(EmlMeta::parse(&m, trusted_authserv_ids)))
         .collect()
 }
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::NotmuchMoreError;
+use crate::config::load_json;
+use crate::config::store_json;
+use crate::parse::RemoteContent;
+
+const POLICIES_FILE: &str = "remote-content.json";
+
+/// Whether to load remote content in messages, per sender address or domain,
+/// stored as JSON in `path`.
+pub struct RemoteContentPolicies {
+    path: PathBuf,
+}
+
+impl RemoteContentPolicies {
+    pub fn new(path: String) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The policy for `sender`'s address, else their domain, else to block.
+    pub fn policy(&self, sender: &str) -> Result<RemoteContent, NotmuchMoreError> {
+        let policies = load_json::<BTreeMap<String, RemoteContent>>(&self.path, POLICIES_FILE)?;
+        let sender = sender.to_lowercase();
+        let domain = sender.rsplit_once('@').map(|(_, d)| d);
+
+        Ok(policies
+            .get(&sender)
+            .or_else(|| domain.and_then(|d| policies.get(d)))
+            .copied()
+            .unwrap_or_default())
+    }
+
+    /// Sets the policy for an address or a whole domain, or removes it.
+    pub fn set_policy(
+        &self,
+        sender_or_domain: &str,
+        policy: Option<RemoteContent>,
+    ) -> Result<(), NotmuchMoreError> {
+        println!("Setting remote content policy for {sender_or_domain} to {policy:?}");
+        let mut policies = load_json::<BTreeMap<String, RemoteContent>>(&self.path, POLICIES_FILE)?;
+        match policy {
+            Some(p) => policies.insert(sender_or_domain.to_lowercase(), p),
+            None => policies.remove(&sender_or_domain.to_lowercase()),
+        };
+        store_json(&self.path, POLICIES_FILE, &policies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_overrides_domain() {
+        let dir = tempfile::tempdir().unwrap();
+        let policies = RemoteContentPolicies::new(dir.path().display().to_string());
+
+        assert_eq!(
+            policies.policy("a@example.com").unwrap(),
+            RemoteContent::Block
+        );
+
+        policies
+            .set_policy("Example.com", Some(RemoteContent::Allow))
+            .unwrap();
+        policies
+            .set_policy("spam@example.com", Some(RemoteContent::Block))
+            .unwrap();
+
+        assert_eq!(
+            policies.policy("A@example.com").unwrap(),
+            RemoteContent::Allow
+        );
+        assert_eq!(
+            policies.policy("spam@example.com").unwrap(),
+            RemoteContent::Block
+        );
+
+        policies.set_policy("example.com", None).unwrap();
+
+        assert_eq!(
+            policies.policy("a@example.com").unwrap(),
+            RemoteContent::Block
+        );
+    }
+}
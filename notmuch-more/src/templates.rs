@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use regex::Captures;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::NotmuchMoreError;
+use crate::config::load_json;
+use crate::config::store_json;
+
+const SIGNATURES_FILE: &str = "signatures.json";
+const TEMPLATES_FILE: &str = "templates.json";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Signature {
+    pub content: String,
+    pub markdown: bool,
+}
+
+/// Reusable message content, which may contain `{{placeholder}}`s.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Template {
+    pub name: String,
+    pub subject: Option<String>,
+    pub body: String,
+    pub markdown: bool,
+}
+
+/// Values for the `{{placeholder}}`s in a template.
+#[derive(Clone, Debug, Default)]
+pub struct Placeholders {
+    pub recipient_name: String,
+    pub original_subject: String,
+    pub date: String,
+}
+
+impl Placeholders {
+    pub fn render(&self, text: &str) -> String {
+        Regex::new(r"\{\{\s*(\w+)\s*\}\}")
+            .unwrap()
+            .replace_all(text, |c: &Captures| match &c[1] {
+                "recipient_name" => self.recipient_name.clone(),
+                "original_subject" => self.original_subject.clone(),
+                "date" => self.date.clone(),
+                _ => c[0].to_string(),
+            })
+            .into()
+    }
+}
+
+/// Signatures (per sending address) and templates, stored as JSON in `path`.
+pub struct Templates {
+    path: PathBuf,
+}
+
+impl Templates {
+    pub fn new(path: String) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn signature(&self, identity: &str) -> Result<Option<Signature>, NotmuchMoreError> {
+        println!("Getting signature for {identity}");
+        Ok(
+            load_json::<BTreeMap<String, Signature>>(&self.path, SIGNATURES_FILE)?
+                .remove(&identity.to_lowercase()),
+        )
+    }
+
+    pub fn set_signature(
+        &self,
+        identity: &str,
+        signature: Option<Signature>,
+    ) -> Result<(), NotmuchMoreError> {
+        println!("Setting signature for {identity}");
+        let mut signatures = load_json::<BTreeMap<String, Signature>>(&self.path, SIGNATURES_FILE)?;
+        match signature {
+            Some(s) => signatures.insert(identity.to_lowercase(), s),
+            None => signatures.remove(&identity.to_lowercase()),
+        };
+        store_json(&self.path, SIGNATURES_FILE, &signatures)
+    }
+
+    pub fn list(&self) -> Result<Vec<Template>, NotmuchMoreError> {
+        println!("Listing templates");
+        Ok(
+            load_json::<BTreeMap<String, Template>>(&self.path, TEMPLATES_FILE)?
+                .into_values()
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, name: &str) -> Result<Template, NotmuchMoreError> {
+        load_json::<BTreeMap<String, Template>>(&self.path, TEMPLATES_FILE)?
+            .remove(name)
+            .ok_or_else(|| anyhow!("Template {} not found", name).into())
+    }
+
+    pub fn save(&self, template: Template) -> Result<(), NotmuchMoreError> {
+        println!("Saving template {}", template.name);
+        let mut templates = load_json::<BTreeMap<String, Template>>(&self.path, TEMPLATES_FILE)?;
+        templates.insert(template.name.clone(), template);
+        store_json(&self.path, TEMPLATES_FILE, &templates)
+    }
+
+    pub fn delete(&self, name: &str) -> Result<(), NotmuchMoreError> {
+        println!("Deleting template {name}");
+        let mut templates = load_json::<BTreeMap<String, Template>>(&self.path, TEMPLATES_FILE)?;
+        templates.remove(name);
+        store_json(&self.path, TEMPLATES_FILE, &templates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_placeholders() {
+        let placeholders = Placeholders {
+            recipient_name: "Lisa".into(),
+            original_subject: "Budget".into(),
+            date: "1 April 2026".into(),
+        };
+
+        assert_eq!(
+            placeholders
+                .render("Hi {{recipient_name}}, re {{ original_subject }} on {{date}} {{unknown}}"),
+            "Hi Lisa, re Budget on 1 April 2026 {{unknown}}",
+        );
+    }
+
+    #[test]
+    fn store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let templates = Templates::new(dir.path().join("amail").display().to_string());
+
+        assert!(templates.list().unwrap().is_empty());
+        assert!(templates.signature("me@example.com").unwrap().is_none());
+
+        templates
+            .save(Template {
+                name: "thanks".into(),
+                body: "Thanks {{recipient_name}}!".into(),
+                ..Default::default()
+            })
+            .unwrap();
+        templates
+            .set_signature(
+                "Me@Example.com",
+                Some(Signature {
+                    content: "Me".into(),
+                    markdown: false,
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            templates.get("thanks").unwrap().body,
+            "Thanks {{recipient_name}}!"
+        );
+        assert_eq!(
+            templates
+                .signature("me@example.com")
+                .unwrap()
+                .unwrap()
+                .content,
+            "Me"
+        );
+
+        templates.delete("thanks").unwrap();
+        templates.set_signature("me@example.com", None).unwrap();
+
+        assert!(templates.get("thanks").is_err());
+        assert!(templates.signature("me@example.com").unwrap().is_none());
+    }
+}
@@ -0,0 +1,408 @@
+use std::fs;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::Local;
+use notmuch::Database;
+use serde::Serialize;
+use url::Url;
+
+use crate::compose::ReplyTemplate;
+use crate::error::NotmuchMoreError;
+use crate::parse::AuthResult;
+use crate::parse::DkimVerdict;
+use crate::parse::EmlAddr;
+use crate::parse::EmlMeta;
+use crate::parse::Mailbox;
+use crate::parse::TxtResolver;
+
+/// The body of an RFC 8058 one-click unsubscribe POST.
+const ONE_CLICK_BODY: &str = "List-Unsubscribe=One-Click";
+
+/// Makes the HTTP requests needed to unsubscribe.
+pub trait HttpClient {
+    /// POSTs `body` as a form to `url`, returning the response's status code.
+    fn post_form(&self, url: &Url, body: &str) -> Result<u16, NotmuchMoreError>;
+}
+
+/// An HTTP client that doesn't follow redirects, as a one-click POST
+/// mustn't be (RFC 8058).
+pub struct SimpleHttpClient {
+    agent: ureq::Agent,
+}
+
+impl Default for SimpleHttpClient {
+    fn default() -> Self {
+        Self {
+            agent: ureq::Agent::config_builder()
+                .timeout_global(Some(Duration::from_secs(30)))
+                .max_redirects(0)
+                .http_status_as_error(false)
+                .build()
+                .into(),
+        }
+    }
+}
+
+impl HttpClient for SimpleHttpClient {
+    fn post_form(&self, url: &Url, body: &str) -> Result<u16, NotmuchMoreError> {
+        let response = self
+            .agent
+            .post(url.as_str())
+            .content_type("application/x-www-form-urlencoded")
+            .send(body)
+            .map_err(|e| anyhow!("Request to {url} failed: {e}"))?;
+        Ok(response.status().as_u16())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum Unsubscribed {
+    /// Done, by a one-click POST to `url`.
+    OneClick { url: String },
+    /// Still to do, by sending this message.
+    Mailto { draft: Box<ReplyTemplate> },
+    /// Still to do, on this web page.
+    Web { url: String },
+}
+
+/// A message to the `mailto:` URL, with the subject and body it asks for.
+fn mailto_draft(url: &Url, meta: &EmlMeta) -> Option<ReplyTemplate> {
+    let to = percent_encoding::percent_decode_str(url.path())
+        .decode_utf8()
+        .ok()?
+        .to_string();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.to_string())
+    };
+    let from = meta.received_by.clone().or_else(|| {
+        meta.to.iter().flatten().find_map(|a| match a {
+            EmlAddr::Single(m) => Some(m.clone()),
+            EmlAddr::Group { .. } => None,
+        })
+    });
+
+    Some(ReplyTemplate {
+        meta: EmlMeta {
+            from: from.into_iter().collect(),
+            subject: Some(param("subject").unwrap_or_else(|| "unsubscribe".into())),
+            timestamp: Local::now().timestamp(),
+            to: Some(vec![EmlAddr::Single(Mailbox {
+                name: "".into(),
+                address: to,
+            })]),
+            ..Default::default()
+        },
+        body: param("body").unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+/// Whether one of the message's `dkim` signatures passed, is aligned with its
+/// From address, and covers both List-Unsubscribe fields, as RFC 8058 (§4)
+/// requires before a one-click POST.
+fn one_click_signed(dkim: &[DkimVerdict]) -> bool {
+    dkim.iter().any(|v| {
+        v.result == AuthResult::Pass
+            && v.aligned
+            && ["list-unsubscribe", "list-unsubscribe-post"]
+                .iter()
+                .all(|h| v.headers.iter().any(|s| s == h))
+    })
+}
+
+/// Unsubscribes from the list `meta` came from: by RFC 8058 one-click POST if
+/// it allows, and its `dkim` signatures vouch for that, else returning what's
+/// needed to do it by email or on the web.
+pub fn unsubscribe(
+    meta: &EmlMeta,
+    dkim: &[DkimVerdict],
+    http: &dyn HttpClient,
+) -> Result<Unsubscribed, NotmuchMoreError> {
+    let list = meta
+        .list
+        .as_ref()
+        .ok_or_else(|| anyhow!("{} isn't from a mailing list", meta.id))?;
+    let urls = list
+        .unsubscribe
+        .iter()
+        .filter_map(|u| Url::parse(u).ok())
+        .collect::<Vec<_>>();
+    let https = urls.iter().find(|u| u.scheme() == "https");
+
+    if let Some(url) = https.filter(|_| list.unsubscribe_one_click && one_click_signed(dkim)) {
+        println!("Unsubscribing from {:?} via {url}", list.id);
+        return match http.post_form(url, ONE_CLICK_BODY)? {
+            200..=299 => Ok(Unsubscribed::OneClick {
+                url: url.to_string(),
+            }),
+            status => Err(anyhow!("Unsubscribe request failed with HTTP {status}").into()),
+        };
+    }
+    if let Some(draft) = urls
+        .iter()
+        .filter(|u| u.scheme() == "mailto")
+        .find_map(|u| mailto_draft(u, meta))
+    {
+        return Ok(Unsubscribed::Mailto {
+            draft: Box::new(draft),
+        });
+    }
+    match https {
+        Some(url) => Ok(Unsubscribed::Web {
+            url: url.to_string(),
+        }),
+        None => Err(anyhow!("No way to unsubscribe from {:?}", list.id).into()),
+    }
+}
+
+/// Unsubscribes from the list message `id` came from, checking its DKIM
+/// signatures with keys from `resolver`.
+pub fn unsubscribe_from(
+    db: &Database,
+    id: &str,
+    http: &dyn HttpClient,
+    resolver: &dyn TxtResolver,
+) -> Result<Unsubscribed, NotmuchMoreError> {
+    let msg = db
+        .find_message(id)?
+        .ok_or_else(|| anyhow!("Message {id} not found"))?;
+    let dkim = crate::parse::verify_dkim(&fs::read(msg.filename())?, resolver);
+    unsubscribe(&crate::parse::parse_meta(db, id.into())?, &dkim, http)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+    use crate::parse::MailingList;
+
+    struct FakeHttpClient {
+        status: u16,
+        requests: RefCell<Vec<(String, String)>>,
+    }
+
+    impl HttpClient for FakeHttpClient {
+        fn post_form(&self, url: &Url, body: &str) -> Result<u16, NotmuchMoreError> {
+            self.requests
+                .borrow_mut()
+                .push((url.to_string(), body.into()));
+            Ok(self.status)
+        }
+    }
+
+    fn signed(headers: &[&str]) -> Vec<DkimVerdict> {
+        vec![DkimVerdict {
+            domain: "x.com".into(),
+            selector: "s".into(),
+            result: AuthResult::Pass,
+            reason: None,
+            aligned: true,
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+        }]
+    }
+
+    const COVERED: &[&str] = &["from", "list-unsubscribe", "list-unsubscribe-post"];
+
+    fn meta(unsubscribe: &[&str], one_click: bool) -> EmlMeta {
+        EmlMeta {
+            id: "1@example.com".into(),
+            list: Some(MailingList {
+                unsubscribe: unsubscribe.iter().map(|u| u.to_string()).collect(),
+                unsubscribe_one_click: one_click,
+                ..Default::default()
+            }),
+            received_by: Some(Mailbox {
+                name: "Me".into(),
+                address: "me@example.org".into(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn one_click_posted() {
+        let http = FakeHttpClient {
+            status: 200,
+            requests: RefCell::new(vec![]),
+        };
+        let meta = meta(&["mailto:u@x.com", "https://x.com/u?id=1"], true);
+
+        assert!(matches!(
+            unsubscribe(&meta, &signed(COVERED), &http).unwrap(),
+            Unsubscribed::OneClick { url } if url == "https://x.com/u?id=1"
+        ));
+        assert_eq!(
+            http.requests.take(),
+            vec![("https://x.com/u?id=1".into(), ONE_CLICK_BODY.into())]
+        );
+
+        let failing = FakeHttpClient {
+            status: 404,
+            requests: RefCell::new(vec![]),
+        };
+        assert!(unsubscribe(&meta, &signed(COVERED), &failing).is_err());
+    }
+
+    #[test]
+    fn one_click_only_if_signed() {
+        let http = FakeHttpClient {
+            status: 200,
+            requests: RefCell::new(vec![]),
+        };
+        let listed = meta(&["https://x.com/u?id=1", "mailto:u@x.com"], true);
+        let mut unaligned = signed(COVERED);
+        unaligned[0].aligned = false;
+        let mut failed = signed(COVERED);
+        failed[0].result = AuthResult::Fail;
+
+        for dkim in [
+            vec![],
+            signed(&["from", "list-unsubscribe"]),
+            unaligned,
+            failed,
+        ] {
+            assert!(matches!(
+                unsubscribe(&listed, &dkim, &http).unwrap(),
+                Unsubscribed::Mailto { .. }
+            ));
+        }
+        assert!(matches!(
+            unsubscribe(&meta(&["https://x.com/u"], true), &[], &http).unwrap(),
+            Unsubscribed::Web { .. }
+        ));
+        assert!(http.requests.take().is_empty());
+    }
+
+    #[test]
+    fn otherwise_mailto_or_web() {
+        let http = FakeHttpClient {
+            status: 200,
+            requests: RefCell::new(vec![]),
+        };
+
+        match unsubscribe(
+            &meta(
+                &[
+                    "https://x.com/u",
+                    "mailto:leave%2Blist@x.com?subject=Leave%20list&body=bye",
+                ],
+                false,
+            ),
+            &signed(COVERED),
+            &http,
+        )
+        .unwrap()
+        {
+            Unsubscribed::Mailto { draft } => {
+                assert_eq!(draft.meta.from[0].address, "me@example.org");
+                assert_eq!(
+                    draft.meta.destinations().unwrap(),
+                    vec!["leave+list@x.com".to_string()]
+                );
+                assert_eq!(draft.meta.subject.as_deref(), Some("Leave list"));
+                assert_eq!(draft.body, "bye");
+            }
+            u => panic!("Unexpected {u:?}"),
+        }
+
+        assert!(matches!(
+            unsubscribe(&meta(&["https://x.com/u"], false), &[], &http).unwrap(),
+            Unsubscribed::Web { .. }
+        ));
+        assert!(http.requests.take().is_empty());
+        assert!(unsubscribe(&EmlMeta::default(), &[], &http).is_err());
+    }
+
+    const ACCEPTED: &str = "HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n";
+
+    /// Serves one request on `listener` with `response`, returning the request.
+    fn serve_one(listener: TcpListener, response: &'static str) -> thread::JoinHandle<String> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            while !request.ends_with(ONE_CLICK_BODY) {
+                let mut buf = [0; 1024];
+                let n = stream.read(&mut buf).unwrap();
+                assert!(n > 0, "Incomplete request: {request}");
+                request.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            request
+        })
+    }
+
+    #[test]
+    fn http_client_posts_form() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = serve_one(listener, ACCEPTED);
+
+        let url = Url::parse(&format!("http://127.0.0.1:{port}/unsub?u=1")).unwrap();
+        assert_eq!(
+            SimpleHttpClient::default()
+                .post_form(&url, ONE_CLICK_BODY)
+                .unwrap(),
+            202
+        );
+
+        let request = server.join().unwrap();
+        assert!(request.ends_with("\r\n\r\nList-Unsubscribe=One-Click"));
+        let request = request.to_lowercase();
+        assert!(request.starts_with("post /unsub?u=1 http/1.1\r\n"));
+        assert!(request.contains(&format!("\r\nhost: 127.0.0.1:{port}\r\n")));
+        assert!(request.contains("\r\ncontent-type: application/x-www-form-urlencoded\r\n"));
+    }
+
+    #[test]
+    fn http_client_not_redirected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = serve_one(
+            listener,
+            "HTTP/1.1 302 Found\r\nLocation: /elsewhere\r\nContent-Length: 0\r\n\r\n",
+        );
+
+        let url = Url::parse(&format!("http://127.0.0.1:{port}/unsub")).unwrap();
+        assert_eq!(
+            SimpleHttpClient::default()
+                .post_form(&url, ONE_CLICK_BODY)
+                .unwrap(),
+            302
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http_client_posts_to_ipv6() {
+        // Not every sandbox has IPv6 loopback.
+        let Ok(listener) = TcpListener::bind("[::1]:0") else {
+            return;
+        };
+        let port = listener.local_addr().unwrap().port();
+        let server = serve_one(listener, ACCEPTED);
+
+        let url = Url::parse(&format!("http://[::1]:{port}/u")).unwrap();
+        assert_eq!(
+            SimpleHttpClient::default()
+                .post_form(&url, ONE_CLICK_BODY)
+                .unwrap(),
+            202
+        );
+        assert!(
+            server
+                .join()
+                .unwrap()
+                .to_lowercase()
+                .contains(&format!("\r\nhost: [::1]:{port}\r\n"))
+        );
+    }
+}